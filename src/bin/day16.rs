@@ -1,12 +1,26 @@
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::io::{self, BufRead, Read};
+use std::ops::RangeInclusive;
+
 const LITERAL_TYPE: usize = 4;
+// a literal with a single group
+const MIN_PACKET_BITS: usize = 11;
 
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 enum Length {
     Bits(usize),
     Packets(usize),
 }
 
+// when encoding only the length type is kept, the length value is recomputed
 #[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(try_from = "UncheckedPacket")
+)]
 enum Packet {
     Literal {
         v: usize,
@@ -31,45 +45,964 @@ impl Packet {
         }
     }
 
-    fn value(&self) -> usize {
+    fn value(&self) -> Result<usize, EvalError> {
+        self.evaluate(&OperatorRegistry::default())
+    }
+
+    fn evaluate(&self, registry: &OperatorRegistry) -> Result<usize, EvalError> {
+        self.evaluate_at(registry, &mut vec![0], None)
+    }
+
+    // records every evaluated operator in evaluation order, on error `trace` ends with the last
+    // successfully evaluated operator
+    fn evaluate_traced(
+        &self,
+        registry: &OperatorRegistry,
+        trace: &mut Vec<TraceStep>,
+    ) -> Result<usize, EvalError> {
+        self.evaluate_at(registry, &mut vec![0], Some(trace))
+    }
+
+    fn evaluate_at(
+        &self,
+        registry: &OperatorRegistry,
+        path: &mut Vec<usize>,
+        mut trace: Option<&mut Vec<TraceStep>>,
+    ) -> Result<usize, EvalError> {
+        match self {
+            Packet::Literal { num, .. } => Ok(*num),
+            Packet::Operator { v, t, packets, .. } => {
+                let mut operands = Vec::with_capacity(packets.len());
+                for (idx, packet) in packets.iter().enumerate() {
+                    path.push(idx);
+                    let value = packet.evaluate_at(registry, path, trace.as_deref_mut());
+                    path.pop();
+                    operands.push(value?);
+                }
+                let result = registry.apply(*t, &operands)?;
+                if let Some(trace) = trace {
+                    trace.push(TraceStep {
+                        path: path.clone(),
+                        v: *v,
+                        t: *t,
+                        operands,
+                        result,
+                    });
+                }
+                Ok(result)
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+struct TraceStep {
+    path: Vec<usize>,
+    v: usize,
+    t: usize,
+    operands: Vec<usize>,
+    result: usize,
+}
+
+impl TraceStep {
+    fn operator_name(&self) -> String {
+        match Operator::from_type_id(self.t) {
+            Some(op) => op.name().to_string(),
+            None => format!("type{}", self.t),
+        }
+    }
+}
+
+fn trace_to_text(trace: &[TraceStep]) -> String {
+    let mut text = String::new();
+    for step in trace {
+        let operands = step
+            .operands
+            .iter()
+            .map(|o| o.to_string())
+            .collect::<Vec<_>>()
+            .join(", ");
+        writeln!(
+            text,
+            "{:indent$}{} v{} {}({}) = {}",
+            "",
+            path_to_string(&step.path),
+            step.v,
+            step.operator_name(),
+            operands,
+            step.result,
+            indent = 2 * (step.path.len() - 1)
+        )
+        .unwrap();
+    }
+    text
+}
+
+fn trace_to_json(trace: &[TraceStep]) -> String {
+    let steps = trace
+        .iter()
+        .map(|step| {
+            format!(
+                "{{\"path\":\"{}\",\"version\":{},\"type\":{},\"operator\":\"{}\",\"operands\":{:?},\"result\":{}}}",
+                path_to_string(&step.path),
+                step.v,
+                step.t,
+                step.operator_name(),
+                step.operands,
+                step.result
+            )
+        })
+        .collect::<Vec<_>>();
+    format!("[{}]", steps.join(","))
+}
+
+fn path_to_string(path: &[usize]) -> String {
+    path.iter()
+        .map(|idx| idx.to_string())
+        .collect::<Vec<_>>()
+        .join(".")
+}
+
+impl Packet {
+    fn version(&self) -> usize {
+        match self {
+            Packet::Literal { v, .. } | Packet::Operator { v, .. } => *v,
+        }
+    }
+
+    fn type_id(&self) -> usize {
+        match self {
+            Packet::Literal { t, .. } | Packet::Operator { t, .. } => *t,
+        }
+    }
+
+    fn sub_packets(&self) -> &[Packet] {
         match self {
-            Packet::Literal { num, .. } => *num,
-            Packet::Operator { t, packets, .. } => match t {
-                0..=3 => {
-                    let all = packets.iter().map(Packet::value);
-                    match t {
-                        0 => all.sum(),
-                        1 => all.product(),
-                        2 => all.min().unwrap(),
-                        3 => all.max().unwrap(),
-                        _ => unreachable!(),
+            Packet::Literal { .. } => &[],
+            Packet::Operator { packets, .. } => packets,
+        }
+    }
+
+    // paths are the same as in traces, root is [0]
+    fn walk(&self, order: Order) -> Walk<'_> {
+        Walk {
+            order,
+            stack: vec![(self, vec![0], false)],
+        }
+    }
+
+    fn find<'a>(
+        &'a self,
+        order: Order,
+        predicate: impl Fn(&Packet) -> bool + 'a,
+    ) -> impl Iterator<Item = (Vec<usize>, &'a Packet)> {
+        self.walk(order)
+            .filter(move |(_, packet)| predicate(packet))
+    }
+
+    fn stats(&self, sizes: Sizes) -> Stats {
+        let mut stats = Stats {
+            bits: sizes.bits,
+            literal_bits: sizes.literal_bits,
+            padding_bits: sizes.padding_bits,
+            ..Stats::default()
+        };
+        for (path, packet) in self.walk(Order::Pre) {
+            stats.packets += 1;
+            stats.max_depth = stats.max_depth.max(path.len());
+            stats.type_counts[packet.type_id()] += 1;
+            stats.versions[packet.version()] += 1;
+        }
+        stats
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Order {
+    Pre,
+    Post,
+}
+
+struct Walk<'a> {
+    order: Order,
+    // packet, its path and whether its sub-packets were already pushed (post order only)
+    stack: Vec<(&'a Packet, Vec<usize>, bool)>,
+}
+
+impl<'a> Walk<'a> {
+    fn push_sub_packets(&mut self, packet: &'a Packet, path: &[usize]) {
+        for (idx, sub_packet) in packet.sub_packets().iter().enumerate().rev() {
+            let mut sub_path = path.to_vec();
+            sub_path.push(idx);
+            self.stack.push((sub_packet, sub_path, false));
+        }
+    }
+}
+
+impl<'a> Iterator for Walk<'a> {
+    type Item = (Vec<usize>, &'a Packet);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let (packet, path, expanded) = self.stack.pop()?;
+            match self.order {
+                Order::Pre => {
+                    self.push_sub_packets(packet, &path);
+                    return Some((path, packet));
+                }
+                Order::Post => {
+                    if expanded || packet.sub_packets().is_empty() {
+                        return Some((path, packet));
                     }
+                    self.stack.push((packet, path.clone(), true));
+                    self.push_sub_packets(packet, &path);
+                }
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+struct Stats {
+    packets: usize,
+    // a lone literal has depth 1
+    max_depth: usize,
+    // indexed by type id
+    type_counts: [usize; 8],
+    // var-int groups of literal values as read, with their continuation bits
+    literal_bits: usize,
+    bits: usize,
+    padding_bits: usize,
+    // indexed by version
+    versions: [usize; 8],
+}
+
+impl std::fmt::Display for Stats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let histogram = |counts: &[usize; 8]| {
+            counts
+                .iter()
+                .enumerate()
+                .map(|(idx, count)| format!("{idx}: {count}"))
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+        writeln!(f, "packets: {}", self.packets)?;
+        writeln!(f, "max depth: {}", self.max_depth)?;
+        writeln!(f, "bits: {} (+{} padding)", self.bits, self.padding_bits)?;
+        writeln!(f, "literal bits: {}", self.literal_bits)?;
+        writeln!(f, "type ids: {}", histogram(&self.type_counts))?;
+        writeln!(f, "versions: {}", histogram(&self.versions))
+    }
+}
+
+fn parse_type_id(s: &str) -> Option<usize> {
+    if s == "literal" {
+        return Some(LITERAL_TYPE);
+    }
+    Operator::ALL
+        .into_iter()
+        .find(|op| op.name() == s)
+        .map(Operator::type_id)
+        .or_else(|| s.parse().ok())
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Arity {
+    AtLeast(usize),
+    Exactly(usize),
+}
+
+impl Arity {
+    fn accepts(self, count: usize) -> bool {
+        match self {
+            Arity::AtLeast(min) => count >= min,
+            Arity::Exactly(n) => count == n,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+enum Operator {
+    Sum,
+    Product,
+    Min,
+    Max,
+    Gt,
+    Lt,
+    Eq,
+}
+
+impl Operator {
+    const ALL: [Operator; 7] = [
+        Operator::Sum,
+        Operator::Product,
+        Operator::Min,
+        Operator::Max,
+        Operator::Gt,
+        Operator::Lt,
+        Operator::Eq,
+    ];
+
+    fn type_id(self) -> usize {
+        match self {
+            Operator::Sum => 0,
+            Operator::Product => 1,
+            Operator::Min => 2,
+            Operator::Max => 3,
+            Operator::Gt => 5,
+            Operator::Lt => 6,
+            Operator::Eq => 7,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Operator::Sum => "sum",
+            Operator::Product => "product",
+            Operator::Min => "min",
+            Operator::Max => "max",
+            Operator::Gt => "gt",
+            Operator::Lt => "lt",
+            Operator::Eq => "eq",
+        }
+    }
+
+    fn from_type_id(t: usize) -> Option<Self> {
+        Self::ALL.into_iter().find(|op| op.type_id() == t)
+    }
+
+    fn arity(self) -> Arity {
+        match self {
+            Operator::Sum | Operator::Product | Operator::Min | Operator::Max => Arity::AtLeast(1),
+            Operator::Gt | Operator::Lt | Operator::Eq => Arity::Exactly(2),
+        }
+    }
+
+    // expects `values.len()` to be accepted by `arity()`, `None` on overflow
+    fn apply(self, values: &[usize]) -> Option<usize> {
+        let mut all = values.iter().copied();
+        let compare = |cond: bool| Some(if cond { 1 } else { 0 });
+        match self {
+            Operator::Sum => all.try_fold(0usize, usize::checked_add),
+            Operator::Product => all.try_fold(1usize, usize::checked_mul),
+            Operator::Min => all.min(),
+            Operator::Max => all.max(),
+            Operator::Gt => compare(values[0] > values[1]),
+            Operator::Lt => compare(values[0] < values[1]),
+            Operator::Eq => compare(values[0] == values[1]),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum EvalError {
+    UnknownType(usize),
+    Arity {
+        t: usize,
+        arity: Arity,
+        count: usize,
+    },
+    Overflow(usize),
+}
+
+impl std::fmt::Display for EvalError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            EvalError::UnknownType(t) => write!(f, "unknown operator type {t}"),
+            EvalError::Arity { t, arity, count } => {
+                let expected = match arity {
+                    Arity::AtLeast(min) => format!("at least {min}"),
+                    Arity::Exactly(n) => n.to_string(),
+                };
+                write!(
+                    f,
+                    "operator type {t} takes {expected} operands, got {count}"
+                )
+            }
+            EvalError::Overflow(t) => write!(f, "operator type {t} overflowed"),
+        }
+    }
+}
+
+type EvalFn = Box<dyn Fn(&[usize]) -> Result<usize, EvalError>>;
+
+// custom operators take precedence over the built-in ones
+#[derive(Default)]
+struct OperatorRegistry {
+    custom: HashMap<usize, (Arity, EvalFn)>,
+}
+
+impl OperatorRegistry {
+    // the type id field is only 3 bits wide and all ids are assigned in the 2021 spec so custom
+    // operators that should survive encoding have to replace a built-in one
+    #[cfg(test)]
+    fn register(
+        &mut self,
+        t: usize,
+        arity: Arity,
+        eval: impl Fn(&[usize]) -> Result<usize, EvalError> + 'static,
+    ) -> &mut Self {
+        assert_ne!(t, LITERAL_TYPE, "literal type id can't be an operator");
+        self.custom.insert(t, (arity, Box::new(eval)));
+        self
+    }
+
+    fn arity(&self, t: usize) -> Option<Arity> {
+        match self.custom.get(&t) {
+            Some((arity, _)) => Some(*arity),
+            None => Operator::from_type_id(t).map(Operator::arity),
+        }
+    }
+
+    fn apply(&self, t: usize, values: &[usize]) -> Result<usize, EvalError> {
+        let arity = self.arity(t).ok_or(EvalError::UnknownType(t))?;
+        if !arity.accepts(values.len()) {
+            return Err(EvalError::Arity {
+                t,
+                arity,
+                count: values.len(),
+            });
+        }
+        match self.custom.get(&t) {
+            Some((_, eval)) => eval(values),
+            None => Operator::from_type_id(t)
+                .unwrap()
+                .apply(values)
+                .ok_or(EvalError::Overflow(t)),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct BitWriter {
+    bytes: Vec<u8>,
+    bits: usize,
+}
+
+impl BitWriter {
+    fn write_bit(&mut self, bit: bool) {
+        if self.bits.is_multiple_of(8) {
+            self.bytes.push(0);
+        }
+        if bit {
+            *self.bytes.last_mut().unwrap() |= 0x80 >> (self.bits % 8);
+        }
+        self.bits += 1;
+    }
+
+    fn write_int(&mut self, value: usize, bits: usize) {
+        assert!(value >> bits == 0, "{value} doesn't fit in {bits} bits");
+        for i in (0..bits).rev() {
+            self.write_bit(value >> i & 1 == 1);
+        }
+    }
+}
+
+fn bytes_to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02X}")).collect()
+}
+
+fn var_int_groups(num: usize) -> usize {
+    ((usize::BITS - num.leading_zeros()) as usize)
+        .max(1)
+        .div_ceil(4)
+}
+
+impl Packet {
+    fn encoded_bits(&self) -> usize {
+        match self {
+            Packet::Literal { num, .. } => 6 + 5 * var_int_groups(*num),
+            Packet::Operator { len, packets, .. } => {
+                let len_bits = match len {
+                    Length::Bits(_) => 15,
+                    Length::Packets(_) => 11,
+                };
+                7 + len_bits + packets.iter().map(Packet::encoded_bits).sum::<usize>()
+            }
+        }
+    }
+
+    // length values are recomputed from the sub-packets, only the length type is kept
+    fn write(&self, writer: &mut BitWriter) {
+        match self {
+            Packet::Literal { v, t, num } => {
+                writer.write_int(*v, 3);
+                writer.write_int(*t, 3);
+                for group in (0..var_int_groups(*num)).rev() {
+                    writer.write_bit(group > 0);
+                    writer.write_int(num >> (4 * group) & 0xF, 4);
                 }
-                5..=7 => {
-                    let left = packets[0].value();
-                    let right = packets[1].value();
-                    if match t {
-                        5 => left > right,
-                        6 => left < right,
-                        7 => left == right,
-                        _ => unreachable!(),
-                    } {
-                        1
-                    } else {
-                        0
+            }
+            Packet::Operator { v, t, len, packets } => {
+                writer.write_int(*v, 3);
+                writer.write_int(*t, 3);
+                match len {
+                    Length::Bits(_) => {
+                        writer.write_bit(false);
+                        writer.write_int(packets.iter().map(Packet::encoded_bits).sum(), 15);
+                    }
+                    Length::Packets(_) => {
+                        writer.write_bit(true);
+                        writer.write_int(packets.len(), 11);
                     }
                 }
-                _ => unreachable!(),
-            },
+                for packet in packets {
+                    packet.write(writer);
+                }
+            }
+        }
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut writer = BitWriter::default();
+        self.write(&mut writer);
+        writer.bytes
+    }
+
+    fn to_hex(&self) -> String {
+        bytes_to_hex(&self.to_bytes())
+    }
+}
+
+// deserialized trees are checked to be encodable, sub-packets are checked on their own
+#[cfg(feature = "serde")]
+#[derive(serde::Deserialize)]
+enum UncheckedPacket {
+    Literal {
+        v: usize,
+        t: usize,
+        num: usize,
+    },
+    Operator {
+        v: usize,
+        t: usize,
+        len: Length,
+        packets: Vec<Packet>,
+    },
+}
+
+#[cfg(feature = "serde")]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum InvalidPacket {
+    Version(usize),
+    TypeId(usize),
+    // a literal with another type id than 4, or an operator with 4
+    Kind(usize),
+    // sub-packets of an operator, in packets or bits depending on its length type
+    Length(Length),
+}
+
+#[cfg(feature = "serde")]
+impl std::fmt::Display for InvalidPacket {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            InvalidPacket::Version(v) => write!(f, "version {v} doesn't fit in 3 bits"),
+            InvalidPacket::TypeId(t) => write!(f, "type id {t} doesn't fit in 3 bits"),
+            InvalidPacket::Kind(t) if *t == LITERAL_TYPE => {
+                write!(f, "type id {t} is only for literals")
+            }
+            InvalidPacket::Kind(t) => write!(f, "type id {t} is not a literal"),
+            InvalidPacket::Length(Length::Packets(count)) => {
+                write!(f, "{count} sub-packets don't fit in an 11 bit count")
+            }
+            InvalidPacket::Length(Length::Bits(bits)) => {
+                write!(f, "{bits} bits of sub-packets don't fit in a 15 bit length")
+            }
         }
     }
 }
 
-struct Parser<Iter> {
-    iter: Iter,
+#[cfg(feature = "serde")]
+impl TryFrom<UncheckedPacket> for Packet {
+    type Error = InvalidPacket;
+
+    fn try_from(packet: UncheckedPacket) -> Result<Self, Self::Error> {
+        let (v, t, is_literal) = match packet {
+            UncheckedPacket::Literal { v, t, .. } => (v, t, true),
+            UncheckedPacket::Operator { v, t, .. } => (v, t, false),
+        };
+        if v >= 8 {
+            return Err(InvalidPacket::Version(v));
+        }
+        if t >= 8 {
+            return Err(InvalidPacket::TypeId(t));
+        }
+        if is_literal != (t == LITERAL_TYPE) {
+            return Err(InvalidPacket::Kind(t));
+        }
+        Ok(match packet {
+            UncheckedPacket::Literal { v, t, num } => Packet::Literal { v, t, num },
+            UncheckedPacket::Operator { v, t, len, packets } => {
+                let needed = match len {
+                    Length::Bits(_) => Length::Bits(packets.iter().map(Packet::encoded_bits).sum()),
+                    Length::Packets(_) => Length::Packets(packets.len()),
+                };
+                if matches!(needed, Length::Bits(bits) if bits >= 1 << 15)
+                    || matches!(needed, Length::Packets(count) if count >= 1 << 11)
+                {
+                    return Err(InvalidPacket::Length(needed));
+                }
+                Packet::Operator { v, t, len, packets }
+            }
+        })
+    }
+}
+
+// picks the cheaper length type, `None` if the sub-packets don't fit either of them
+fn cheapest_length(packets: &[Packet]) -> Option<Length> {
+    if packets.len() < 1 << 11 {
+        return Some(Length::Packets(packets.len()));
+    }
+    let bits = packets.iter().map(Packet::encoded_bits).sum::<usize>();
+    if bits < 1 << 15 {
+        Some(Length::Bits(bits))
+    } else {
+        None
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+struct Optimized {
+    packet: Packet,
+    bits_before: usize,
+    bits_after: usize,
+}
+
+impl Optimized {
+    fn bits_saved(&self) -> isize {
+        self.bits_before as isize - self.bits_after as isize
+    }
+}
+
+impl Packet {
+    // keeps the value with built-in operators, versions of folded operators are dropped
+    fn optimized(&self) -> Packet {
+        match self {
+            // the encoder always picks the minimal var-int
+            Packet::Literal { .. } => self.clone(),
+            Packet::Operator { v, t, len, packets } => {
+                let packets: Vec<Packet> = packets.iter().map(Packet::optimized).collect();
+                let variadic = Operator::from_type_id(*t)
+                    .map(|op| op.arity() == Arity::AtLeast(1))
+                    .unwrap_or(false);
+                if variadic && packets.len() == 1 {
+                    return packets.into_iter().next().unwrap();
+                }
+                if variadic {
+                    let flattened: Vec<Packet> = packets
+                        .iter()
+                        .flat_map(|p| match p {
+                            Packet::Operator {
+                                t: inner_t,
+                                packets: inner_packets,
+                                ..
+                            } if inner_t == t => inner_packets.clone(),
+                            _ => vec![p.clone()],
+                        })
+                        .collect();
+                    if let Some(len) = cheapest_length(&flattened) {
+                        return Packet::Operator {
+                            v: *v,
+                            t: *t,
+                            len,
+                            packets: flattened,
+                        };
+                    }
+                }
+                Packet::Operator {
+                    v: *v,
+                    t: *t,
+                    len: cheapest_length(&packets).unwrap_or(*len),
+                    packets,
+                }
+            }
+        }
+    }
+
+    // `bits_before` is the size of the packet as it was read, var-ints may have had extra groups
+    fn optimize(&self, bits_before: usize) -> Optimized {
+        let packet = self.optimized();
+        let bits_after = packet.encoded_bits();
+        Optimized {
+            packet,
+            bits_before,
+            bits_after,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Encoding {
+    Hex,
+    Binary,
+}
+
+// how back-to-back transmissions are separated, both pad each transmission to a whole byte
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Framing {
+    // the next transmission starts right after the padding
+    Packed,
+    // any number of zero bytes may follow the padding, so no transmission may start with one
+    ZeroSeparated,
+}
+
+fn unexpected_eof() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::UnexpectedEof,
+        "transmission ended mid-packet",
+    )
+}
+
+trait BitSource {
+    fn at_end(&mut self) -> io::Result<bool>;
+
+    // reads up to 56 bits as a big endian number
+    fn read_bits(&mut self, bits: usize) -> io::Result<usize>;
+
+    // like `read_bits` without consuming anything, None if there aren't that many bits left
+    fn peek_bits(&mut self, bits: usize) -> io::Result<Option<usize>>;
+
+    // moves on to the next segment of the input (a line of hex) once at the end of the current
+    // one, false when there is none
+    fn next_segment(&mut self) -> io::Result<bool> {
+        Ok(false)
+    }
+}
+
+// bit by bit reference implementation
+#[cfg(test)]
+impl<Iter: Iterator<Item = bool> + Clone> BitSource for std::iter::Peekable<Iter> {
+    fn at_end(&mut self) -> io::Result<bool> {
+        Ok(self.peek().is_none())
+    }
+
+    fn peek_bits(&mut self, bits: usize) -> io::Result<Option<usize>> {
+        Ok(self.clone().read_bits(bits).ok())
+    }
+
+    fn read_bits(&mut self, bits: usize) -> io::Result<usize> {
+        let mut result = 0;
+        for _ in 0..bits {
+            result <<= 1;
+            result |= if self.next().ok_or_else(unexpected_eof)? {
+                1
+            } else {
+                0
+            };
+        }
+        Ok(result)
+    }
+}
+
+const BIT_READER_BUFFER_SIZE: usize = 64 * 1024;
+
+// keeps up to 64 not yet consumed bits left aligned in `word`, refilled from a byte buffer
+struct BitReader<R> {
+    reader: R,
+    buffer: Box<[u8]>,
+    pos: usize,
+    len: usize,
+    eof: bool,
+    word: u64,
+    word_bits: usize,
+}
+
+impl<R: Read> BitReader<R> {
+    fn new(reader: R) -> Self {
+        Self {
+            reader,
+            buffer: vec![0; BIT_READER_BUFFER_SIZE].into_boxed_slice(),
+            pos: 0,
+            len: 0,
+            eof: false,
+            word: 0,
+            word_bits: 0,
+        }
+    }
+
+    fn fill_buffer(&mut self) -> io::Result<()> {
+        self.buffer.copy_within(self.pos..self.len, 0);
+        self.len -= self.pos;
+        self.pos = 0;
+        loop {
+            match self.reader.read(&mut self.buffer[self.len..]) {
+                Ok(0) => self.eof = true,
+                Ok(read) => self.len += read,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            }
+            return Ok(());
+        }
+    }
+
+    fn refill(&mut self) -> io::Result<()> {
+        if self.len - self.pos < 8 && !self.eof {
+            self.fill_buffer()?;
+        }
+        if self.len - self.pos >= 8 {
+            // bits past `word_bits` are also copied but they're exactly the bits next refill would put there
+            let next = u64::from_be_bytes(self.buffer[self.pos..self.pos + 8].try_into().unwrap());
+            self.word |= next >> self.word_bits;
+            let bytes = (63 - self.word_bits) / 8;
+            self.pos += bytes;
+            self.word_bits += bytes * 8;
+        } else {
+            while self.word_bits <= 56 && self.pos < self.len {
+                self.word |= (self.buffer[self.pos] as u64) << (56 - self.word_bits);
+                self.pos += 1;
+                self.word_bits += 8;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<R: Read> BitSource for BitReader<R> {
+    fn at_end(&mut self) -> io::Result<bool> {
+        if self.word_bits == 0 {
+            self.refill()?;
+        }
+        Ok(self.word_bits == 0)
+    }
+
+    fn read_bits(&mut self, bits: usize) -> io::Result<usize> {
+        assert!(bits <= 56);
+        if bits == 0 {
+            return Ok(0);
+        }
+        if self.word_bits < bits {
+            self.refill()?;
+            if self.word_bits < bits {
+                return Err(unexpected_eof());
+            }
+        }
+        let result = self.word >> (64 - bits);
+        self.word <<= bits;
+        self.word_bits -= bits;
+        Ok(result as usize)
+    }
+
+    fn peek_bits(&mut self, bits: usize) -> io::Result<Option<usize>> {
+        assert!(bits > 0 && bits <= 56);
+        if self.word_bits < bits {
+            self.refill()?;
+        }
+        Ok((self.word_bits >= bits).then(|| (self.word >> (64 - bits)) as usize))
+    }
+
+    // a reader ends a segment by returning 0 once, a plain reader just keeps doing that
+    fn next_segment(&mut self) -> io::Result<bool> {
+        if !self.at_end()? {
+            return Ok(true);
+        }
+        self.eof = false;
+        self.fill_buffer()?;
+        Ok(self.len > self.pos)
+    }
+}
+
+// decodes hex text into bytes, every line is a segment of its own: a read returns 0 once at its
+// end and then carries on with the next line. An odd digit at the end of a line is padded with
+// 0, other whitespace and blank lines are skipped
+struct HexBytes<R> {
+    reader: io::BufReader<R>,
+    pending: Option<u8>,
+    line_has_digits: bool,
+    end_of_line: bool,
+}
+
+impl<R: Read> HexBytes<R> {
+    fn new(reader: R) -> Self {
+        Self {
+            reader: io::BufReader::new(reader),
+            pending: None,
+            line_has_digits: false,
+            end_of_line: false,
+        }
+    }
+}
+
+impl<R: Read> Read for HexBytes<R> {
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        if out.is_empty() {
+            return Ok(0);
+        }
+        loop {
+            if self.end_of_line {
+                if let Some(high) = self.pending.take() {
+                    out[0] = high << 4;
+                    return Ok(1);
+                }
+                self.end_of_line = false;
+                return Ok(0);
+            }
+            let input = self.reader.fill_buf()?;
+            if input.is_empty() {
+                if !self.line_has_digits {
+                    return Ok(0);
+                }
+                self.line_has_digits = false;
+                self.end_of_line = true;
+                continue;
+            }
+            let mut consumed = 0;
+            let mut written = 0;
+            for &c in input {
+                if written == out.len() {
+                    break;
+                }
+                consumed += 1;
+                if c == b'\n' && self.line_has_digits {
+                    self.line_has_digits = false;
+                    self.end_of_line = true;
+                    break;
+                }
+                if c.is_ascii_whitespace() {
+                    continue;
+                }
+                let digit = (c as char).to_digit(16).ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("invalid hex digit: {:?}", c as char),
+                    )
+                })? as u8;
+                self.line_has_digits = true;
+                match self.pending.take() {
+                    Some(high) => {
+                        out[written] = high << 4 | digit;
+                        written += 1;
+                    }
+                    None => self.pending = Some(digit),
+                }
+            }
+            self.reader.consume(consumed);
+            if written > 0 {
+                return Ok(written);
+            }
+        }
+    }
+}
+
+struct Parser<Bits> {
+    bits: Bits,
     bits_read: usize,
+    // var-int groups of literal values, with their continuation bits
+    literal_bits_read: usize,
+    framing: Framing,
+}
+
+// sizes of a transmission as read, in bits
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+struct Sizes {
+    // without padding
+    bits: usize,
+    literal_bits: usize,
+    // up to the next transmission or the end of the segment
+    padding_bits: usize,
 }
 
+#[cfg(test)]
 fn hex2bits(hex: char) -> [bool; 4] {
     let v = match hex {
         '0'..='9' => hex as u8 - b'0',
@@ -79,87 +1012,643 @@ fn hex2bits(hex: char) -> [bool; 4] {
     [v & 8 > 0, v & 4 > 0, v & 2 > 0, v & 1 > 0]
 }
 
-fn parser_from_str(input: &'static str) -> Parser<impl Iterator<Item = bool>> {
-    Parser::new(input.chars().flat_map(hex2bits))
+fn parser_from_str(input: &str) -> Parser<impl BitSource + '_> {
+    Parser::new(BitReader::new(HexBytes::new(input.as_bytes())))
+}
+
+fn parser_from_reader<'a, R: Read + 'a>(
+    reader: R,
+    encoding: Encoding,
+) -> Parser<BitReader<Box<dyn Read + 'a>>> {
+    let reader: Box<dyn Read + 'a> = match encoding {
+        Encoding::Hex => Box::new(HexBytes::new(reader)),
+        Encoding::Binary => Box::new(reader),
+    };
+    Parser::new(BitReader::new(reader))
 }
 
-impl<Iter> Parser<Iter>
+impl<Bits> Parser<Bits>
 where
-    Iter: Iterator<Item = bool>,
+    Bits: BitSource,
 {
-    fn new(iter: Iter) -> Self {
-        Self { iter, bits_read: 0 }
+    fn new(bits: Bits) -> Self {
+        Self {
+            bits,
+            bits_read: 0,
+            literal_bits_read: 0,
+            framing: Framing::Packed,
+        }
     }
 
-    fn get_bit(&mut self) -> bool {
-        self.bits_read += 1;
-        self.iter.next().unwrap()
+    fn with_framing(mut self, framing: Framing) -> Self {
+        self.framing = framing;
+        self
     }
 
-    fn read_int(&mut self, bits: usize) -> usize {
-        let mut result = 0;
-        for _ in 0..bits {
-            result <<= 1;
-            result |= if self.get_bit() { 1 } else { 0 };
-        }
-        result
+    fn at_end(&mut self) -> io::Result<bool> {
+        self.bits.at_end()
     }
 
-    fn read_var_int(&mut self) -> usize {
+    fn get_bit(&mut self) -> io::Result<bool> {
+        Ok(self.read_int(1)? == 1)
+    }
+
+    fn read_int(&mut self, bits: usize) -> io::Result<usize> {
+        let result = self.bits.read_bits(bits)?;
+        self.bits_read += bits;
+        Ok(result)
+    }
+
+    fn read_var_int(&mut self) -> io::Result<usize> {
         let mut result = 0;
-        while self.get_bit() {
+        loop {
+            let group = self.read_int(5)?;
+            self.literal_bits_read += 5;
             result <<= 4;
-            result |= self.read_int(4);
+            result |= group & 0xF;
+            if group & 0x10 == 0 {
+                return Ok(result);
+            }
         }
-        result <<= 4;
-        result |= self.read_int(4);
-        result
     }
 
-    fn read_packet(&mut self) -> Packet {
-        let v = self.read_int(3);
-        let t = self.read_int(3);
+    fn read_packet(&mut self) -> io::Result<Packet> {
+        let v = self.read_int(3)?;
+        let t = self.read_int(3)?;
         if t == LITERAL_TYPE {
-            let num = self.read_var_int();
-            Packet::Literal { v, t, num }
+            let num = self.read_var_int()?;
+            Ok(Packet::Literal { v, t, num })
         } else {
-            let len = if self.get_bit() {
-                Length::Packets(self.read_int(11))
+            let len = if self.get_bit()? {
+                Length::Packets(self.read_int(11)?)
             } else {
-                Length::Bits(self.read_int(15))
+                Length::Bits(self.read_int(15)?)
             };
-            Packet::Operator {
+            Ok(Packet::Operator {
                 v,
                 t,
                 len,
-                packets: self.read_packets(len),
+                packets: self.read_packets(len)?,
+            })
+        }
+    }
+
+    fn read_packets(&mut self, len: Length) -> io::Result<Vec<Packet>> {
+        let mut packets = Vec::new();
+        match len {
+            Length::Bits(bits) => {
+                let end = self.bits_read + bits;
+                while self.bits_read < end {
+                    packets.push(self.read_packet()?);
+                }
+                if self.bits_read != end {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "sub-packets overrun declared length",
+                    ));
+                }
+            }
+            Length::Packets(count) => {
+                for _ in 0..count {
+                    packets.push(self.read_packet()?);
+                }
+            }
+        }
+        Ok(packets)
+    }
+
+    // transmissions are padded with zeros to a whole byte, see `Framing` for what may follow.
+    // The rest of a segment too short for another packet is padding too, like the extra digit
+    // of a hex line with an odd length
+    fn skip_padding(&mut self) -> io::Result<()> {
+        while !self.bits_read.is_multiple_of(8) && !self.at_end()? {
+            self.skip_zero_bit()?;
+        }
+        if self.bits.peek_bits(MIN_PACKET_BITS)?.is_none() {
+            while !self.at_end()? {
+                self.skip_zero_bit()?;
+            }
+        }
+        if self.framing == Framing::ZeroSeparated {
+            while self.bits.peek_bits(8)? == Some(0) {
+                self.read_int(8)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn skip_zero_bit(&mut self) -> io::Result<()> {
+        if self.get_bit()? {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "non-zero padding after transmission",
+            ));
+        }
+        Ok(())
+    }
+
+    fn read_transmission(&mut self) -> io::Result<Option<(Packet, Sizes)>> {
+        while self.at_end()? {
+            if !self.bits.next_segment()? {
+                return Ok(None);
+            }
+        }
+        let start = self.bits_read;
+        let literal_start = self.literal_bits_read;
+        let packet = self.read_packet()?;
+        let end = self.bits_read;
+        self.skip_padding()?;
+        let sizes = Sizes {
+            bits: end - start,
+            literal_bits: self.literal_bits_read - literal_start,
+            padding_bits: self.bits_read - end,
+        };
+        Ok(Some((packet, sizes)))
+    }
+
+    // yields top level packets of back-to-back transmissions with their sizes, stops after the first error
+    fn transmissions(mut self) -> impl Iterator<Item = io::Result<(Packet, Sizes)>> {
+        let mut failed = false;
+        std::iter::from_fn(move || {
+            if failed {
+                return None;
+            }
+            let result = self.read_transmission().transpose();
+            failed = matches!(result, Some(Err(_)));
+            result
+        })
+    }
+
+    fn packets(self) -> impl Iterator<Item = io::Result<Packet>> {
+        self.transmissions()
+            .map(|transmission| transmission.map(|(packet, _)| packet))
+    }
+}
+
+// splitmix64, generated transmissions only depend on the seed
+struct Rng(u64);
+
+impl Rng {
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E3779B97F4A7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+        z ^ (z >> 31)
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        (self.next_u64() % n as u64) as usize
+    }
+
+    fn range(&mut self, range: &RangeInclusive<usize>) -> usize {
+        range.start() + self.below(range.end() - range.start() + 1)
+    }
+
+    fn chance(&mut self, p: f64) -> bool {
+        ((self.next_u64() >> 11) as f64 / (1u64 << 53) as f64) < p
+    }
+
+    fn weighted(&mut self, weights: &[u32]) -> usize {
+        let total: u32 = weights.iter().sum();
+        assert!(total > 0, "all weights are zero");
+        let mut pick = self.below(total as usize) as u32;
+        for (idx, &weight) in weights.iter().enumerate() {
+            if pick < weight {
+                return idx;
             }
+            pick -= weight;
+        }
+        unreachable!()
+    }
+}
+
+#[derive(Clone, Debug)]
+struct GeneratorConfig {
+    // only literals at this depth
+    max_depth: usize,
+    // sub-packets of sum, product, min and max, comparisons always get two
+    fan_out: RangeInclusive<usize>,
+    // significant bits of literal numbers
+    literal_bits: RangeInclusive<usize>,
+    // relative weights of type ids 0..=7, 4 being literals
+    type_weights: [u32; 8],
+    // probability of `Length::Bits` where the sub-packets fit in it
+    bits_length_chance: f64,
+    malformation_chance: f64,
+}
+
+impl Default for GeneratorConfig {
+    fn default() -> Self {
+        Self {
+            max_depth: 4,
+            fan_out: 1..=4,
+            literal_bits: 1..=16,
+            type_weights: [1, 1, 1, 1, 4, 1, 1, 1],
+            bits_length_chance: 0.5,
+            malformation_chance: 0.0,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Malformation {
+    // cut inside the packet, decoding hits the end of the transmission
+    Truncated,
+    // every 3-bit type id is assigned so a bad type is a comparison with other than two
+    // sub-packets, decoding succeeds but evaluation fails
+    BadType,
+    // a bit length shorter than the sub-packets, decoding fails
+    BadLength,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+struct Generated {
+    // for malformed transmissions the tree the transmission was made from
+    packet: Packet,
+    hex: String,
+    version_sum: usize,
+    // `None` if evaluation is expected to fail
+    value: Option<usize>,
+    malformation: Option<Malformation>,
+}
+
+struct Generator {
+    config: GeneratorConfig,
+    rng: Rng,
+}
+
+impl Generator {
+    fn new(seed: u64, config: GeneratorConfig) -> Self {
+        Self {
+            config,
+            rng: Rng(seed),
+        }
+    }
+
+    // returns the packet with its value, operators which would overflow become min
+    fn node(&mut self, depth: usize) -> (Packet, usize) {
+        let v = self.rng.below(8);
+        let t = if depth >= self.config.max_depth {
+            LITERAL_TYPE
+        } else {
+            self.rng.weighted(&self.config.type_weights)
+        };
+        if t == LITERAL_TYPE {
+            let bits = self.rng.range(&self.config.literal_bits).min(64);
+            let num = if bits == 0 {
+                0
+            } else {
+                (self.rng.next_u64() >> (64 - bits)) as usize
+            };
+            return (Packet::Literal { v, t, num }, num);
+        }
+        let mut op = Operator::from_type_id(t).unwrap();
+        let count = match op.arity() {
+            Arity::Exactly(count) => count,
+            Arity::AtLeast(min) => self.rng.range(&self.config.fan_out).clamp(min, 2047),
+        };
+        let (packets, values): (Vec<Packet>, Vec<usize>) =
+            (0..count).map(|_| self.node(depth + 1)).unzip();
+        let value = op.apply(&values).unwrap_or_else(|| {
+            op = Operator::Min;
+            op.apply(&values).unwrap()
+        });
+        let bits = packets.iter().map(Packet::encoded_bits).sum::<usize>();
+        let len = if bits < 1 << 15 && self.rng.chance(self.config.bits_length_chance) {
+            Length::Bits(bits)
+        } else {
+            Length::Packets(packets.len())
+        };
+        let packet = Packet::Operator {
+            v,
+            t: op.type_id(),
+            len,
+            packets,
+        };
+        (packet, value)
+    }
+
+    fn transmission(&mut self) -> Generated {
+        let (packet, value) = self.node(0);
+        let malformation = if self.rng.chance(self.config.malformation_chance) {
+            Some(
+                [
+                    Malformation::Truncated,
+                    Malformation::BadType,
+                    Malformation::BadLength,
+                ][self.rng.below(3)],
+            )
+        } else {
+            None
+        };
+        let (packet, hex, value) = match malformation {
+            None => {
+                let hex = packet.to_hex();
+                (packet, hex, Some(value))
+            }
+            Some(Malformation::Truncated) => {
+                let keep = 1 + self.rng.below((packet.encoded_bits() - 1) / 8);
+                let hex = bytes_to_hex(&packet.to_bytes()[..keep]);
+                (packet, hex, Some(value))
+            }
+            Some(Malformation::BadType) => {
+                let mut packets = vec![packet];
+                if self.rng.chance(0.5) {
+                    let zero = Packet::Literal {
+                        v: 0,
+                        t: LITERAL_TYPE,
+                        num: 0,
+                    };
+                    packets.push(zero.clone());
+                    packets.push(zero);
+                }
+                let packet = Packet::Operator {
+                    v: self.rng.below(8),
+                    t: [Operator::Gt, Operator::Lt, Operator::Eq][self.rng.below(3)].type_id(),
+                    len: Length::Packets(packets.len()),
+                    packets,
+                };
+                let hex = packet.to_hex();
+                (packet, hex, None)
+            }
+            Some(Malformation::BadLength) => {
+                let bits = packet.encoded_bits();
+                let v = self.rng.below(8);
+                let mut writer = BitWriter::default();
+                writer.write_int(v, 3);
+                writer.write_int(Operator::Sum.type_id(), 3);
+                writer.write_bit(false);
+                writer.write_int(1 + self.rng.below(bits.min(1 << 15) - 1), 15);
+                packet.write(&mut writer);
+                let hex = bytes_to_hex(&writer.bytes);
+                let packet = Packet::Operator {
+                    v,
+                    t: Operator::Sum.type_id(),
+                    len: Length::Bits(bits),
+                    packets: vec![packet],
+                };
+                (packet, hex, Some(value))
+            }
+        };
+        Generated {
+            version_sum: packet.version_sum(),
+            packet,
+            hex,
+            value,
+            malformation,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Mode {
+    Parts,
+    Optimize,
+    Trace,
+    TraceJson,
+    Stats,
+    Find {
+        t: usize,
+        v: Option<usize>,
+        order: Order,
+    },
+    #[cfg(feature = "serde")]
+    Json,
+    #[cfg(feature = "serde")]
+    Bincode,
+}
+
+#[derive(Debug)]
+enum RunError {
+    Io(io::Error),
+    Eval(EvalError),
+}
+
+impl From<io::Error> for RunError {
+    fn from(e: io::Error) -> Self {
+        RunError::Io(e)
+    }
+}
+
+impl From<EvalError> for RunError {
+    fn from(e: EvalError) -> Self {
+        RunError::Eval(e)
+    }
+}
+
+impl std::fmt::Display for RunError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RunError::Io(e) => write!(f, "reading input failed: {e}"),
+            RunError::Eval(e) => write!(f, "evaluation failed: {e}"),
         }
     }
+}
 
-    fn read_packets(&mut self, len: Length) -> Vec<Packet> {
-        let mut packets = Vec::new();
-        match len {
-            Length::Bits(bits) => {
-                let current_bits_read = self.bits_read;
-                while self.bits_read < current_bits_read + bits {
-                    packets.push(self.read_packet());
+// stops at the first transmission that can't be read or evaluated
+fn run<Bits: BitSource>(parser: Parser<Bits>, mode: Mode) -> Result<(), RunError> {
+    match mode {
+        Mode::Parts => {
+            for packet in parser.packets() {
+                let packet = packet?;
+                println!("Part 1: {}", packet.version_sum());
+                println!("Part 2: {}", packet.value()?);
+            }
+        }
+        Mode::Optimize => {
+            for transmission in parser.transmissions() {
+                let (packet, sizes) = transmission?;
+                let value = packet.value()?;
+                let optimized = packet.optimize(sizes.bits);
+                assert_eq!(optimized.packet.value(), Ok(value));
+                println!(
+                    "{} -> {} bits (saved {})",
+                    optimized.bits_before,
+                    optimized.bits_after,
+                    optimized.bits_saved()
+                );
+                println!("{}", optimized.packet.to_hex());
+            }
+        }
+        Mode::Stats => {
+            for transmission in parser.transmissions() {
+                let (packet, sizes) = transmission?;
+                print!("{}", packet.stats(sizes));
+            }
+        }
+        Mode::Find { t, v, order } => {
+            for packet in parser.packets() {
+                let packet = packet?;
+                let matches = packet.find(order, |p| {
+                    p.type_id() == t && v.map(|v| p.version() == v).unwrap_or(true)
+                });
+                for (path, found) in matches {
+                    println!("{} v{}", path_to_string(&path), found.version());
                 }
             }
-            Length::Packets(count) => {
-                for _ in 0..count {
-                    packets.push(self.read_packet());
+        }
+        Mode::Trace | Mode::TraceJson => {
+            for packet in parser.packets() {
+                let mut trace = Vec::new();
+                let result = packet?.evaluate_traced(&OperatorRegistry::default(), &mut trace);
+                if mode == Mode::Trace {
+                    print!("{}", trace_to_text(&trace));
+                } else {
+                    println!("{}", trace_to_json(&trace));
                 }
+                result?;
+            }
+        }
+        #[cfg(feature = "serde")]
+        Mode::Json => {
+            for packet in parser.packets() {
+                println!("{}", serde_json::to_string(&packet?).unwrap());
+            }
+        }
+        #[cfg(feature = "serde")]
+        Mode::Bincode => {
+            let mut stdout = io::stdout().lock();
+            for packet in parser.packets() {
+                bincode::serialize_into(&mut stdout, &packet?).unwrap();
             }
         }
-        packets
+    }
+    Ok(())
+}
+
+// encodes a stream of JSON packets into hex transmissions, one per line, up to the first
+// packet that can't be read or encoded
+#[cfg(feature = "serde")]
+fn encode_json<R: Read>(reader: R) -> Result<(), serde_json::Error> {
+    for packet in serde_json::Deserializer::from_reader(reader).into_iter::<Packet>() {
+        println!("{}", packet?.to_hex());
+    }
+    Ok(())
+}
+
+// prints hex, version sum, value and malformation of each generated transmission
+fn generate(args: &[String], malformed: bool) {
+    let mut positional = args
+        .iter()
+        .filter(|arg| !arg.starts_with("--"))
+        .map(|arg| arg.parse::<u64>().unwrap());
+    let seed = positional.next().unwrap_or(2021);
+    let count = positional.next().unwrap_or(1);
+    let config = GeneratorConfig {
+        malformation_chance: if malformed { 0.25 } else { 0.0 },
+        ..GeneratorConfig::default()
+    };
+    let mut generator = Generator::new(seed, config);
+    for _ in 0..count {
+        let generated = generator.transmission();
+        println!(
+            "{}\t{}\t{}\t{}",
+            generated.hex,
+            generated.version_sum,
+            generated
+                .value
+                .map_or_else(|| "-".to_string(), |v| v.to_string()),
+            generated
+                .malformation
+                .map_or_else(|| "-".to_string(), |m| format!("{m:?}"))
+        );
     }
 }
 
+// usage: day16 [--optimize|--trace|--trace-json|--stats|--json|--bincode]
+//              [FILE [--binary] [--zero-separated]]
+//        day16 --find=TYPE[:VERSION] [--post-order] [FILE [--binary] [--zero-separated]]
+//        day16 --from-json [FILE]
+//        day16 --generate [SEED [COUNT]] [--malformed]
+// FILE may contain multiple back-to-back transmissions, a line break in hex always ends one and
+// --zero-separated skips zero bytes between them, --json, --bincode and --from-json need the
+// serde feature
 fn main() {
-    let packet = parser_from_str(include_str!("../../inputs/day16.txt")).read_packet();
-    println!("Part 1: {}", packet.version_sum());
-    println!("Part 2: {}", packet.value());
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let flag = |name: &str| args.iter().any(|arg| arg == name);
+    let path = args.iter().find(|arg| !arg.starts_with("--"));
+    if flag("--generate") {
+        generate(&args, flag("--malformed"));
+        return;
+    }
+    #[cfg(feature = "serde")]
+    if flag("--from-json") {
+        let result = match path {
+            Some(path) => std::fs::File::open(path)
+                .map_err(serde_json::Error::io)
+                .and_then(encode_json),
+            None => encode_json(io::stdin()),
+        };
+        if let Err(e) = result {
+            eprintln!("encoding failed: {e}");
+            std::process::exit(1);
+        }
+        return;
+    }
+    let mode = if flag("--optimize") {
+        Mode::Optimize
+    } else if flag("--trace") {
+        Mode::Trace
+    } else if flag("--trace-json") {
+        Mode::TraceJson
+    } else if flag("--stats") {
+        Mode::Stats
+    } else if let Some(query) = args.iter().find_map(|arg| arg.strip_prefix("--find=")) {
+        let (t, v) = match query.split_once(':') {
+            Some((t, v)) => (t, Some(v.parse().unwrap())),
+            None => (query, None),
+        };
+        Mode::Find {
+            t: parse_type_id(t).unwrap(),
+            v,
+            order: if flag("--post-order") {
+                Order::Post
+            } else {
+                Order::Pre
+            },
+        }
+    } else {
+        Mode::Parts
+    };
+    #[cfg(feature = "serde")]
+    let mode = if flag("--json") {
+        Mode::Json
+    } else if flag("--bincode") {
+        Mode::Bincode
+    } else {
+        mode
+    };
+    let result = match path {
+        None => run(
+            parser_from_str(include_str!("../../inputs/day16.txt")),
+            mode,
+        ),
+        Some(path) => {
+            let encoding = if flag("--binary") {
+                Encoding::Binary
+            } else {
+                Encoding::Hex
+            };
+            let framing = if flag("--zero-separated") {
+                Framing::ZeroSeparated
+            } else {
+                Framing::Packed
+            };
+            std::fs::File::open(path)
+                .map_err(RunError::from)
+                .and_then(|file| {
+                    run(
+                        parser_from_reader(file, encoding).with_framing(framing),
+                        mode,
+                    )
+                })
+        }
+    };
+    if let Err(e) = result {
+        eprintln!("{e}");
+        std::process::exit(1);
+    }
 }
 
 #[cfg(test)]
@@ -183,15 +1672,15 @@ mod tests {
     #[test]
     fn test_read_int() {
         let mut parser = parser_from_str("0ABC");
-        assert_eq!(parser.read_int(4), 0);
-        assert_eq!(parser.read_int(4), 0xA);
-        assert_eq!(parser.read_int(4), 0xB);
+        assert_eq!(parser.read_int(4).unwrap(), 0);
+        assert_eq!(parser.read_int(4).unwrap(), 0xA);
+        assert_eq!(parser.read_int(4).unwrap(), 0xB);
     }
 
     #[test]
     fn test_parse_sample_literal() {
         assert_eq!(
-            parser_from_str("D2FE28").read_packet(),
+            parser_from_str("D2FE28").read_packet().unwrap(),
             Packet::Literal {
                 v: 6,
                 t: 4,
@@ -203,7 +1692,7 @@ mod tests {
     #[test]
     fn test_parse_sample_operator() {
         assert_eq!(
-            parser_from_str("38006F45291200").read_packet(),
+            parser_from_str("38006F45291200").read_packet().unwrap(),
             Packet::Operator {
                 v: 1,
                 t: 6,
@@ -227,7 +1716,7 @@ mod tests {
     #[test]
     fn test_parse_sample_operator2() {
         assert_eq!(
-            parser_from_str("EE00D40C823060").read_packet(),
+            parser_from_str("EE00D40C823060").read_packet().unwrap(),
             Packet::Operator {
                 v: 7,
                 t: 3,
@@ -246,24 +1735,28 @@ mod tests {
         assert_eq!(
             parser_from_str("8A004A801A8002F478")
                 .read_packet()
+                .unwrap()
                 .version_sum(),
             16
         );
         assert_eq!(
             parser_from_str("620080001611562C8802118E34")
                 .read_packet()
+                .unwrap()
                 .version_sum(),
             12
         );
         assert_eq!(
             parser_from_str("C0015000016115A2E0802F182340")
                 .read_packet()
+                .unwrap()
                 .version_sum(),
             23
         );
         assert_eq!(
             parser_from_str("A0016C880162017C3686B18A3D4780")
                 .read_packet()
+                .unwrap()
                 .version_sum(),
             31
         );
@@ -271,18 +1764,886 @@ mod tests {
 
     #[test]
     fn test_value() {
-        assert_eq!(parser_from_str("C200B40A82").read_packet().value(), 3);
-        assert_eq!(parser_from_str("04005AC33890").read_packet().value(), 54);
-        assert_eq!(parser_from_str("880086C3E88112").read_packet().value(), 7);
-        assert_eq!(parser_from_str("CE00C43D881120").read_packet().value(), 9);
-        assert_eq!(parser_from_str("D8005AC2A8F0").read_packet().value(), 1);
-        assert_eq!(parser_from_str("F600BC2D8F").read_packet().value(), 0);
-        assert_eq!(parser_from_str("9C005AC2F8F0").read_packet().value(), 0);
+        assert_eq!(
+            parser_from_str("C200B40A82").read_packet().unwrap().value(),
+            Ok(3)
+        );
+        assert_eq!(
+            parser_from_str("04005AC33890")
+                .read_packet()
+                .unwrap()
+                .value(),
+            Ok(54)
+        );
+        assert_eq!(
+            parser_from_str("880086C3E88112")
+                .read_packet()
+                .unwrap()
+                .value(),
+            Ok(7)
+        );
+        assert_eq!(
+            parser_from_str("CE00C43D881120")
+                .read_packet()
+                .unwrap()
+                .value(),
+            Ok(9)
+        );
+        assert_eq!(
+            parser_from_str("D8005AC2A8F0")
+                .read_packet()
+                .unwrap()
+                .value(),
+            Ok(1)
+        );
+        assert_eq!(
+            parser_from_str("F600BC2D8F").read_packet().unwrap().value(),
+            Ok(0)
+        );
+        assert_eq!(
+            parser_from_str("9C005AC2F8F0")
+                .read_packet()
+                .unwrap()
+                .value(),
+            Ok(0)
+        );
         assert_eq!(
             parser_from_str("9C0141080250320F1802104A08")
                 .read_packet()
+                .unwrap()
                 .value(),
-            1
+            Ok(1)
+        );
+    }
+
+    fn hex2bytes(hex: &str) -> Vec<u8> {
+        (0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_reader_hex_and_binary() {
+        for hex in ["D2FE28", "38006F45291200", "9C0141080250320F1802104A08"] {
+            let expected = parser_from_str(hex).read_packet().unwrap();
+            assert_eq!(
+                parser_from_reader(hex.as_bytes(), Encoding::Hex)
+                    .read_packet()
+                    .unwrap(),
+                expected
+            );
+            assert_eq!(
+                parser_from_reader(hex2bytes(hex).as_slice(), Encoding::Binary)
+                    .read_packet()
+                    .unwrap(),
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn test_stream_multiple_transmissions() {
+        let hexes = ["D2FE28", "38006F45291200", "EE00D40C823060", "C200B40A82"];
+        let expected: Vec<Packet> = hexes
+            .iter()
+            .map(|hex| parser_from_str(hex).read_packet().unwrap())
+            .collect();
+
+        let text = hexes.join("\n") + "\n";
+        let packets = parser_from_reader(text.as_bytes(), Encoding::Hex)
+            .packets()
+            .collect::<io::Result<Vec<_>>>()
+            .unwrap();
+        assert_eq!(packets, expected);
+
+        let bytes: Vec<u8> = hexes.iter().flat_map(|hex| hex2bytes(hex)).collect();
+        let packets = parser_from_reader(bytes.as_slice(), Encoding::Binary)
+            .packets()
+            .collect::<io::Result<Vec<_>>>()
+            .unwrap();
+        assert_eq!(packets, expected);
+    }
+
+    #[test]
+    fn test_stream_errors() {
+        let mut packets = parser_from_reader("D2FE".as_bytes(), Encoding::Hex).packets();
+        assert_eq!(
+            packets.next().unwrap().unwrap_err().kind(),
+            io::ErrorKind::UnexpectedEof
+        );
+        assert!(packets.next().is_none());
+
+        let mut packets = parser_from_reader("D2FE29D2FE28".as_bytes(), Encoding::Hex).packets();
+        assert_eq!(
+            packets.next().unwrap().unwrap_err().kind(),
+            io::ErrorKind::InvalidData
+        );
+        assert!(packets.next().is_none());
+
+        let mut packets = parser_from_reader("D2FG28".as_bytes(), Encoding::Hex).packets();
+        assert_eq!(
+            packets.next().unwrap().unwrap_err().kind(),
+            io::ErrorKind::InvalidData
+        );
+    }
+
+    #[test]
+    fn test_stream_padding() {
+        let parse = |text: &str, framing| {
+            parser_from_reader(text.as_bytes(), Encoding::Hex)
+                .with_framing(framing)
+                .packets()
+                .collect::<io::Result<Vec<_>>>()
+        };
+        let literal = parser_from_str("D2FE28").read_packet().unwrap();
+        let operator = parser_from_str("38006F45291200").read_packet().unwrap();
+        // version 0 sum with a bit length, starts with a zero byte
+        let sum = parser_from_str("000058408820").read_packet().unwrap();
+
+        assert_eq!(
+            parse("D2FE28000058408820", Framing::Packed).unwrap(),
+            [literal.clone(), sum.clone()]
+        );
+        // trailing zero bytes are padding, not empty operators
+        assert_eq!(
+            parse("D2FE28000000000000", Framing::ZeroSeparated).unwrap(),
+            std::slice::from_ref(&literal)
+        );
+        assert_eq!(
+            parse("38006F4529120000\nD2FE28", Framing::ZeroSeparated).unwrap(),
+            [operator.clone(), literal.clone()]
+        );
+        // an odd digit doesn't carry over to the next line
+        assert_eq!(
+            parse("D2FE280\nD2FE28\r\n\n", Framing::Packed).unwrap(),
+            [literal.clone(), literal.clone()]
+        );
+        // a line ends its transmission
+        assert_eq!(
+            parse("D2FE\nD2FE28", Framing::Packed).unwrap_err().kind(),
+            io::ErrorKind::UnexpectedEof
+        );
+
+        let binary = |hexes: &[&str], framing| {
+            let bytes: Vec<u8> = hexes.iter().flat_map(|hex| hex2bytes(hex)).collect();
+            parser_from_reader(bytes.as_slice(), Encoding::Binary)
+                .with_framing(framing)
+                .packets()
+                .collect::<io::Result<Vec<_>>>()
+                .unwrap()
+        };
+        assert_eq!(
+            binary(&["D2FE28", "000058408820"], Framing::Packed),
+            [literal.clone(), sum]
+        );
+        assert_eq!(
+            binary(
+                &["D2FE28", "000000", "38006F45291200"],
+                Framing::ZeroSeparated
+            ),
+            [literal, operator]
+        );
+    }
+
+    #[test]
+    fn test_stream_generated_transmissions() {
+        let mut generator = Generator::new(26, GeneratorConfig::default());
+        let generated: Vec<Generated> = (0..200).map(|_| generator.transmission()).collect();
+        assert!(generated.iter().any(|g| g.hex.starts_with("00")));
+        let expected: Vec<Packet> = generated.iter().map(|g| g.packet.clone()).collect();
+
+        let hex: String = generated.iter().map(|g| g.hex.as_str()).collect();
+        let packets = parser_from_reader(hex.as_bytes(), Encoding::Hex)
+            .packets()
+            .collect::<io::Result<Vec<_>>>()
+            .unwrap();
+        assert_eq!(packets, expected);
+
+        let bytes = hex2bytes(&hex);
+        let packets = parser_from_reader(bytes.as_slice(), Encoding::Binary)
+            .packets()
+            .collect::<io::Result<Vec<_>>>()
+            .unwrap();
+        assert_eq!(packets, expected);
+    }
+
+    #[test]
+    fn test_run_errors() {
+        assert!(matches!(
+            run(parser_from_str("D2FE"), Mode::Parts),
+            Err(RunError::Io(e)) if e.kind() == io::ErrorKind::UnexpectedEof
+        ));
+        assert!(matches!(
+            run(parser_from_str("EE00D40C823060"), Mode::Parts),
+            Ok(())
+        ));
+        let literal = |num| Packet::Literal {
+            v: 0,
+            t: LITERAL_TYPE,
+            num,
+        };
+        let lt = Packet::Operator {
+            v: 0,
+            t: Operator::Lt.type_id(),
+            len: Length::Packets(3),
+            packets: vec![literal(1), literal(2), literal(3)],
+        };
+        assert!(matches!(
+            run(parser_from_str(&lt.to_hex()), Mode::Parts),
+            Err(RunError::Eval(EvalError::Arity { .. }))
+        ));
+    }
+
+    fn reference_parser_from_str(input: &str) -> Parser<impl BitSource + '_> {
+        Parser::new(input.chars().flat_map(hex2bits).peekable())
+    }
+
+    fn tree(seed: u64, max_depth: usize, fan_out: usize) -> Packet {
+        Generator::new(
+            seed,
+            GeneratorConfig {
+                max_depth,
+                fan_out: fan_out..=fan_out,
+                literal_bits: 0..=28,
+                type_weights: [1, 1, 1, 1, 0, 0, 0, 0],
+                ..GeneratorConfig::default()
+            },
+        )
+        .transmission()
+        .packet
+    }
+
+    #[test]
+    fn test_encode_samples() {
+        for hex in ["D2FE28", "38006F45291200", "EE00D40C823060", "C200B40A82"] {
+            assert_eq!(parser_from_str(hex).read_packet().unwrap().to_hex(), hex);
+        }
+    }
+
+    #[test]
+    fn test_bit_reader_matches_reference() {
+        let bytes: Vec<u8> = (0..1000u32).map(|i| (i * 97 % 251) as u8).collect();
+        let hex: String = bytes.iter().map(|b| format!("{b:02X}")).collect();
+        let mut fast = BitReader::new(bytes.as_slice());
+        let mut reference = hex.chars().flat_map(hex2bits).peekable();
+        let mut width = 0;
+        while !reference.at_end().unwrap() {
+            width = width % 56 + 1;
+            let expected = reference.read_bits(width);
+            let actual = fast.read_bits(width);
+            match expected {
+                Ok(expected) => assert_eq!(actual.unwrap(), expected),
+                Err(_) => {
+                    assert_eq!(actual.unwrap_err().kind(), io::ErrorKind::UnexpectedEof);
+                    break;
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_parser_matches_reference() {
+        let trees = [
+            parser_from_str("9C0141080250320F1802104A08")
+                .read_packet()
+                .unwrap(),
+            tree(16, 2, 5),
+            tree(17, 3, 12),
+            tree(18, 1, 2000),
+        ];
+        for tree in trees {
+            let hex = tree.to_hex();
+            let expected = reference_parser_from_str(&hex).read_packet().unwrap();
+            assert_eq!(expected, tree);
+            assert_eq!(parser_from_str(&hex).read_packet().unwrap(), expected);
+            assert_eq!(
+                parser_from_reader(tree.to_bytes().as_slice(), Encoding::Binary)
+                    .read_packet()
+                    .unwrap(),
+                expected
+            );
+        }
+    }
+
+    // cargo test --release --bin day16 -- --ignored --nocapture
+    #[test]
+    #[ignore]
+    fn bench_large_transmission() {
+        let tree = tree(2021, 4, 30);
+        let bytes = tree.to_bytes();
+        let hex = tree.to_hex();
+        println!("transmission: {} bytes", bytes.len());
+
+        let time = |name: &str, parse: &dyn Fn() -> Packet| {
+            let start = std::time::Instant::now();
+            assert_eq!(parse(), tree);
+            let elapsed = start.elapsed();
+            println!(
+                "{name}: {elapsed:?} ({:.1} MB/s)",
+                bytes.len() as f64 / elapsed.as_secs_f64() / 1e6
+            );
+        };
+        time("bit by bit, hex", &|| {
+            reference_parser_from_str(&hex).read_packet().unwrap()
+        });
+        time("bit reader, hex", &|| {
+            parser_from_str(&hex).read_packet().unwrap()
+        });
+        time("bit reader, binary", &|| {
+            parser_from_reader(bytes.as_slice(), Encoding::Binary)
+                .read_packet()
+                .unwrap()
+        });
+    }
+
+    #[test]
+    fn test_operator_type_ids() {
+        for op in Operator::ALL {
+            assert_eq!(Operator::from_type_id(op.type_id()), Some(op));
+        }
+        assert_eq!(Operator::from_type_id(LITERAL_TYPE), None);
+        assert_eq!(Operator::from_type_id(8), None);
+    }
+
+    #[test]
+    fn test_evaluate_arity() {
+        let registry = OperatorRegistry::default();
+        // 7 (==) with three sub-packets
+        let packet = Packet::Operator {
+            v: 0,
+            t: 7,
+            len: Length::Packets(3),
+            packets: vec![
+                Packet::Literal { v: 0, t: 4, num: 1 },
+                Packet::Literal { v: 0, t: 4, num: 1 },
+                Packet::Literal { v: 0, t: 4, num: 1 },
+            ],
+        };
+        assert_eq!(
+            packet.evaluate(&registry),
+            Err(EvalError::Arity {
+                t: 7,
+                arity: Arity::Exactly(2),
+                count: 3
+            })
+        );
+        let packet = Packet::Operator {
+            v: 0,
+            t: 2,
+            len: Length::Packets(0),
+            packets: vec![],
+        };
+        assert!(packet.evaluate(&registry).is_err());
+        let packet = Packet::Operator {
+            v: 0,
+            t: 9,
+            len: Length::Packets(0),
+            packets: vec![],
+        };
+        assert_eq!(packet.evaluate(&registry), Err(EvalError::UnknownType(9)));
+    }
+
+    #[test]
+    fn test_custom_operator() {
+        let mut registry = OperatorRegistry::default();
+        // 6 becomes "distance" and 9 a variadic xor (only representable in trees, not on the wire)
+        registry
+            .register(6, Arity::Exactly(2), |values| {
+                Ok(values[0].abs_diff(values[1]))
+            })
+            .register(9, Arity::AtLeast(0), |values| {
+                Ok(values.iter().fold(0, |acc, v| acc ^ v))
+            });
+        let packet = parser_from_str("D8005AC2A8F0").read_packet().unwrap();
+        assert_eq!(packet.value(), Ok(1));
+        assert_eq!(packet.evaluate(&registry), Ok(15 - 5));
+
+        let packet = Packet::Operator {
+            v: 0,
+            t: 9,
+            len: Length::Packets(2),
+            packets: vec![
+                Packet::Literal { v: 0, t: 4, num: 6 },
+                Packet::Literal { v: 0, t: 4, num: 3 },
+            ],
+        };
+        assert_eq!(packet.evaluate(&registry), Ok(5));
+
+        // and custom operators fail like the built-in ones
+        registry.register(0, Arity::AtLeast(1), |values| {
+            values
+                .iter()
+                .try_fold(0usize, |acc, &v| acc.checked_sub(v))
+                .ok_or(EvalError::Overflow(0))
+        });
+        let packet = parser_from_str("C200B40A82").read_packet().unwrap();
+        assert_eq!(packet.evaluate(&registry), Err(EvalError::Overflow(0)));
+        assert_eq!(
+            EvalError::Overflow(0).to_string(),
+            "operator type 0 overflowed"
+        );
+    }
+
+    #[test]
+    fn test_optimize() {
+        let literal = |num| Packet::Literal {
+            v: 1,
+            t: LITERAL_TYPE,
+            num,
+        };
+        // sum(1, sum(2, 3), max(product(4, product(5))))
+        let packet = Packet::Operator {
+            v: 1,
+            t: 0,
+            len: Length::Bits(0),
+            packets: vec![
+                literal(1),
+                Packet::Operator {
+                    v: 2,
+                    t: 0,
+                    len: Length::Bits(0),
+                    packets: vec![literal(2), literal(3)],
+                },
+                Packet::Operator {
+                    v: 3,
+                    t: 3,
+                    len: Length::Packets(1),
+                    packets: vec![Packet::Operator {
+                        v: 4,
+                        t: 1,
+                        len: Length::Bits(0),
+                        packets: vec![
+                            literal(4),
+                            Packet::Operator {
+                                v: 5,
+                                t: 1,
+                                len: Length::Packets(1),
+                                packets: vec![literal(5)],
+                            },
+                        ],
+                    }],
+                },
+            ],
+        };
+        let optimized = packet.optimize(packet.encoded_bits());
+        assert_eq!(
+            optimized.packet,
+            Packet::Operator {
+                v: 1,
+                t: 0,
+                len: Length::Packets(4),
+                packets: vec![
+                    literal(1),
+                    literal(2),
+                    literal(3),
+                    Packet::Operator {
+                        v: 4,
+                        t: 1,
+                        len: Length::Packets(2),
+                        packets: vec![literal(4), literal(5)],
+                    },
+                ],
+            }
+        );
+        assert_eq!(optimized.packet.value(), packet.value());
+        assert_eq!(optimized.bits_after, optimized.packet.encoded_bits());
+        assert_eq!(
+            optimized.bits_saved(),
+            (packet.encoded_bits() - optimized.packet.encoded_bits()) as isize
+        );
+    }
+
+    #[test]
+    fn test_optimize_keeps_values() {
+        for hex in [
+            "C200B40A82",
+            "04005AC33890",
+            "880086C3E88112",
+            "CE00C43D881120",
+            "D8005AC2A8F0",
+            "F600BC2D8F",
+            "9C005AC2F8F0",
+            "9C0141080250320F1802104A08",
+            include_str!("../../inputs/day16.txt"),
+        ] {
+            let (packet, sizes) = parser_from_str(hex).read_transmission().unwrap().unwrap();
+            let optimized = packet.optimize(sizes.bits);
+            assert!(optimized.bits_saved() >= 0);
+            let reparsed = parser_from_str(&optimized.packet.to_hex())
+                .read_packet()
+                .unwrap();
+            assert_eq!(reparsed, optimized.packet);
+            assert_eq!(reparsed.value(), packet.value());
+        }
+    }
+
+    #[test]
+    fn test_optimize_var_int() {
+        // literal 2021 with two extra leading zero groups
+        let hex = "D210BF8A";
+        let (packet, sizes) = parser_from_str(hex).read_transmission().unwrap().unwrap();
+        assert_eq!(packet, parser_from_str("D2FE28").read_packet().unwrap());
+        assert_eq!(sizes.bits, 31);
+        assert_eq!(packet.optimize(sizes.bits).bits_saved(), 10);
+    }
+
+    #[test]
+    fn test_trace() {
+        // 1 + 3 = 2 * 2
+        let packet = parser_from_str("9C0141080250320F1802104A08")
+            .read_packet()
+            .unwrap();
+        let mut trace = Vec::new();
+        assert_eq!(
+            packet.evaluate_traced(&OperatorRegistry::default(), &mut trace),
+            Ok(1)
+        );
+        assert_eq!(
+            trace,
+            vec![
+                TraceStep {
+                    path: vec![0, 0],
+                    v: 2,
+                    t: 0,
+                    operands: vec![1, 3],
+                    result: 4,
+                },
+                TraceStep {
+                    path: vec![0, 1],
+                    v: 6,
+                    t: 1,
+                    operands: vec![2, 2],
+                    result: 4,
+                },
+                TraceStep {
+                    path: vec![0],
+                    v: 4,
+                    t: 7,
+                    operands: vec![4, 4],
+                    result: 1,
+                },
+            ]
+        );
+        assert_eq!(
+            trace_to_text(&trace),
+            "  0.0 v2 sum(1, 3) = 4\n  0.1 v6 product(2, 2) = 4\n0 v4 eq(4, 4) = 1\n"
+        );
+        assert_eq!(
+            trace_to_json(&trace[2..]),
+            r#"[{"path":"0","version":4,"type":7,"operator":"eq","operands":[4, 4],"result":1}]"#
+        );
+    }
+
+    #[test]
+    fn test_trace_stops_at_error() {
+        let packet = Packet::Operator {
+            v: 1,
+            t: 0,
+            len: Length::Packets(2),
+            packets: vec![
+                Packet::Operator {
+                    v: 2,
+                    t: 3,
+                    len: Length::Packets(1),
+                    packets: vec![Packet::Literal { v: 3, t: 4, num: 7 }],
+                },
+                Packet::Operator {
+                    v: 4,
+                    t: 5,
+                    len: Length::Packets(0),
+                    packets: vec![],
+                },
+            ],
+        };
+        let mut trace = Vec::new();
+        assert!(packet
+            .evaluate_traced(&OperatorRegistry::default(), &mut trace)
+            .is_err());
+        assert_eq!(trace_to_text(&trace), "  0.0 v2 max(7) = 7\n");
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde_json() {
+        let packet = parser_from_str("38006F45291200").read_packet().unwrap();
+        let json = serde_json::to_string(&packet).unwrap();
+        assert_eq!(
+            json,
+            r#"{"Operator":{"v":1,"t":6,"len":{"Bits":27},"packets":[{"Literal":{"v":6,"t":4,"num":10}},{"Literal":{"v":2,"t":4,"num":20}}]}}"#
+        );
+        assert_eq!(serde_json::from_str::<Packet>(&json).unwrap(), packet);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde_json_authored_tree() {
+        // length values don't have to be right, the encoder recomputes them
+        let packet: Packet = serde_json::from_str(
+            r#"{"Operator":{"v":7,"t":3,"len":{"Packets":0},"packets":[
+                {"Literal":{"v":2,"t":4,"num":1}},
+                {"Literal":{"v":4,"t":4,"num":2}},
+                {"Literal":{"v":1,"t":4,"num":3}}
+            ]}}"#,
+        )
+        .unwrap();
+        assert_eq!(packet.to_hex(), "EE00D40C823060");
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde_json_rejects_unencodable_trees() {
+        let error = |json: &str| {
+            serde_json::from_str::<Packet>(json)
+                .unwrap_err()
+                .to_string()
+        };
+        assert!(error(r#"{"Literal":{"v":9,"t":4,"num":1}}"#)
+            .starts_with("version 9 doesn't fit in 3 bits"));
+        assert!(
+            error(r#"{"Literal":{"v":1,"t":0,"num":1}}"#).starts_with("type id 0 is not a literal")
+        );
+        assert!(
+            error(r#"{"Operator":{"v":1,"t":4,"len":{"Packets":0},"packets":[]}}"#)
+                .starts_with("type id 4 is only for literals")
+        );
+        assert!(
+            error(r#"{"Operator":{"v":1,"t":8,"len":{"Packets":0},"packets":[]}}"#)
+                .starts_with("type id 8 doesn't fit in 3 bits")
+        );
+        // checked at any depth
+        assert!(error(
+            r#"{"Operator":{"v":1,"t":0,"len":{"Packets":1},"packets":[
+                {"Literal":{"v":1,"t":4,"num":1}},
+                {"Literal":{"v":1,"t":5,"num":1}}
+            ]}}"#
+        )
+        .starts_with("type id 5 is not a literal"));
+
+        let literal = r#"{"Literal":{"v":0,"t":4,"num":0}}"#;
+        let operator = |len: &str, count: usize| {
+            format!(
+                r#"{{"Operator":{{"v":0,"t":0,"len":{{"{len}":0}},"packets":[{}]}}}}"#,
+                vec![literal; count].join(",")
+            )
+        };
+        assert!(serde_json::from_str::<Packet>(&operator("Packets", 2047)).is_ok());
+        assert!(error(&operator("Packets", 2048))
+            .starts_with("2048 sub-packets don't fit in an 11 bit count"));
+        // 11 bits per literal, 2978 * 11 = 32758
+        assert!(serde_json::from_str::<Packet>(&operator("Bits", 2978)).is_ok());
+        assert!(error(&operator("Bits", 2979))
+            .starts_with("32769 bits of sub-packets don't fit in a 15 bit length"));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde_bincode() {
+        let packet = parser_from_str(include_str!("../../inputs/day16.txt"))
+            .read_packet()
+            .unwrap();
+        let bytes = bincode::serialize(&packet).unwrap();
+        assert_eq!(bincode::deserialize::<Packet>(&bytes).unwrap(), packet);
+    }
+
+    #[test]
+    fn test_generator_is_seeded() {
+        let generate = |seed| {
+            let mut generator = Generator::new(seed, GeneratorConfig::default());
+            (0..10)
+                .map(|_| generator.transmission())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(generate(1), generate(1));
+        assert_ne!(generate(1), generate(2));
+    }
+
+    #[test]
+    fn test_generated_transmissions() {
+        let mut generator = Generator::new(
+            2021,
+            GeneratorConfig {
+                max_depth: 6,
+                fan_out: 1..=6,
+                literal_bits: 0..=64,
+                ..GeneratorConfig::default()
+            },
+        );
+        for _ in 0..200 {
+            let generated = generator.transmission();
+            let packet = parser_from_str(&generated.hex).read_packet().unwrap();
+            assert_eq!(packet, generated.packet);
+            assert_eq!(packet.version_sum(), generated.version_sum);
+            assert_eq!(generated.value, packet.value().ok());
+        }
+    }
+
+    #[test]
+    fn test_generated_malformations() {
+        let mut generator = Generator::new(
+            7,
+            GeneratorConfig {
+                malformation_chance: 1.0,
+                ..GeneratorConfig::default()
+            },
+        );
+        let mut seen = Vec::new();
+        for _ in 0..100 {
+            let generated = generator.transmission();
+            let decoded = parser_from_str(&generated.hex).read_packet();
+            match generated.malformation.unwrap() {
+                Malformation::Truncated => {
+                    assert_eq!(decoded.unwrap_err().kind(), io::ErrorKind::UnexpectedEof)
+                }
+                Malformation::BadLength => {
+                    assert_eq!(decoded.unwrap_err().kind(), io::ErrorKind::InvalidData)
+                }
+                Malformation::BadType => {
+                    let packet = decoded.unwrap();
+                    assert_eq!(packet, generated.packet);
+                    assert_eq!(packet.version_sum(), generated.version_sum);
+                    assert_eq!(generated.value, None);
+                    assert!(matches!(
+                        packet.evaluate(&OperatorRegistry::default()),
+                        Err(EvalError::Arity { .. })
+                    ));
+                }
+            }
+            seen.push(generated.malformation);
+        }
+        assert!(seen.contains(&Some(Malformation::Truncated)));
+        assert!(seen.contains(&Some(Malformation::BadType)));
+        assert!(seen.contains(&Some(Malformation::BadLength)));
+    }
+
+    #[test]
+    fn test_walk() {
+        // sum(1, 3) == product(2, 2)
+        let packet = parser_from_str("9C0141080250320F1802104A08")
+            .read_packet()
+            .unwrap();
+        let paths = |order| {
+            packet
+                .walk(order)
+                .map(|(path, p)| (path_to_string(&path), p.type_id()))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            paths(Order::Pre),
+            vec![
+                ("0".to_string(), 7),
+                ("0.0".to_string(), 0),
+                ("0.0.0".to_string(), 4),
+                ("0.0.1".to_string(), 4),
+                ("0.1".to_string(), 1),
+                ("0.1.0".to_string(), 4),
+                ("0.1.1".to_string(), 4),
+            ]
+        );
+        assert_eq!(
+            paths(Order::Post),
+            vec![
+                ("0.0.0".to_string(), 4),
+                ("0.0.1".to_string(), 4),
+                ("0.0".to_string(), 0),
+                ("0.1.0".to_string(), 4),
+                ("0.1.1".to_string(), 4),
+                ("0.1".to_string(), 1),
+                ("0".to_string(), 7),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_find() {
+        let packet = parser_from_str(include_str!("../../inputs/day16.txt"))
+            .read_packet()
+            .unwrap();
+        let lt_v7 = packet
+            .find(Order::Pre, |p| {
+                p.type_id() == Operator::Lt.type_id() && p.version() == 7
+            })
+            .collect::<Vec<_>>();
+        assert!(!lt_v7.is_empty());
+        for (path, found) in lt_v7 {
+            let mut node = &packet;
+            for idx in &path[1..] {
+                node = &node.sub_packets()[*idx];
+            }
+            assert_eq!(node, found);
+            assert_eq!(found.type_id(), 6);
+            assert_eq!(found.version(), 7);
+        }
+    }
+
+    #[test]
+    fn test_stats() {
+        let (packet, sizes) = parser_from_str("8A004A801A8002F478")
+            .read_transmission()
+            .unwrap()
+            .unwrap();
+        let stats = packet.stats(sizes);
+        assert_eq!(
+            stats,
+            Stats {
+                packets: 4,
+                max_depth: 4,
+                type_counts: [0, 0, 3, 0, 1, 0, 0, 0],
+                literal_bits: 5,
+                bits: 69,
+                padding_bits: 3,
+                versions: [0, 1, 0, 0, 1, 1, 1, 0],
+            }
+        );
+        assert_eq!(stats.versions.iter().sum::<usize>(), stats.packets);
+        assert_eq!(
+            stats
+                .versions
+                .iter()
+                .enumerate()
+                .map(|(v, count)| v * count)
+                .sum::<usize>(),
+            packet.version_sum()
+        );
+    }
+
+    #[test]
+    fn test_stats_sizes_as_read() {
+        let sizes = |text: &str, framing| {
+            parser_from_reader(text.as_bytes(), Encoding::Hex)
+                .with_framing(framing)
+                .transmissions()
+                .map(|transmission| transmission.unwrap().1)
+                .collect::<Vec<_>>()
+        };
+        // 2021 in three groups, then with two leading zero groups
+        assert_eq!(
+            sizes("D2FE28\nD210BF8A", Framing::Packed),
+            [
+                Sizes {
+                    bits: 21,
+                    literal_bits: 15,
+                    padding_bits: 3,
+                },
+                Sizes {
+                    bits: 31,
+                    literal_bits: 25,
+                    padding_bits: 1,
+                },
+            ]
+        );
+        let packet = parser_from_str("D210BF8A").read_packet().unwrap();
+        assert_eq!(
+            packet
+                .stats(sizes("D210BF8A", Framing::Packed)[0])
+                .literal_bits,
+            25
+        );
+        // the extra digit of an odd line and skipped zero bytes are padding too
+        assert_eq!(sizes("D2FE280", Framing::Packed)[0].padding_bits, 11);
+        assert_eq!(
+            sizes("D2FE280000D2FE28", Framing::ZeroSeparated)[0].padding_bits,
+            19
         );
     }
 }
@@ -1,4 +1,9 @@
-use std::ops::RangeBounds;
+use once_cell::sync::Lazy;
+use regex::Regex;
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
+use std::io::{self, Write};
+use std::ops::{Bound, RangeBounds, RangeInclusive};
 
 #[derive(Clone, Debug, Eq, PartialEq)]
 struct Status {
@@ -6,86 +11,1004 @@ struct Status {
     reached_target: bool,
 }
 
-fn unwrap_bound<T>(bound: std::ops::Bound<T>) -> T {
-    match bound {
-        std::ops::Bound::Included(x) => x,
-        std::ops::Bound::Excluded(x) => x,
-        _ => unreachable!(),
+// inclusive, `None` is unbounded
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+struct Interval {
+    min: Option<isize>,
+    max: Option<isize>,
+}
+
+impl Interval {
+    fn from_range<R: RangeBounds<isize>>(range: &R) -> Self {
+        Self {
+            min: match range.start_bound() {
+                Bound::Included(x) => Some(*x),
+                Bound::Excluded(x) => Some(x + 1),
+                Bound::Unbounded => None,
+            },
+            max: match range.end_bound() {
+                Bound::Included(x) => Some(*x),
+                Bound::Excluded(x) => Some(x - 1),
+                Bound::Unbounded => None,
+            },
+        }
+    }
+
+    fn contains(&self, v: isize) -> bool {
+        self.min.is_none_or(|min| min <= v) && self.max.is_none_or(|max| v <= max)
+    }
+
+    fn is_empty(&self) -> bool {
+        matches!((self.min, self.max), (Some(min), Some(max)) if min > max)
+    }
+
+    fn is_bounded(&self) -> bool {
+        self.min.is_some() && self.max.is_some()
+    }
+
+    // largest absolute value of the finite bounds
+    fn max_abs(&self) -> isize {
+        [self.min, self.max]
+            .into_iter()
+            .flatten()
+            .map(isize::abs)
+            .max()
+            .unwrap_or(0)
     }
 }
 
-fn simulate<XRange: RangeBounds<isize>, YRange: RangeBounds<isize>>(
-    mut vx: isize,
-    mut vy: isize,
-    tx: &XRange,
-    ty: &YRange,
-) -> Status {
-    let mut x = 0;
-    let mut y = 0;
-    let mut highest = 0;
-    let mut reached_target = false;
-    let deepest = *unwrap_bound(ty.start_bound());
-    let max_x = *unwrap_bound(tx.end_bound());
-    while !reached_target && !(vx == 0 && (y < deepest || !tx.contains(&x))) && x <= max_x {
-        x += vx;
-        y += vy;
-        vx -= vx.signum();
-        vy -= 1;
-        if highest < y {
-            highest = y;
-        }
-        if tx.contains(&x) && ty.contains(&y) {
-            reached_target = true;
+fn triangle(n: isize) -> isize {
+    n * (n + 1) / 2
+}
+
+// position after `n` steps along the drag axis
+fn x_after(vx: isize, n: isize) -> isize {
+    let speed = vx.abs();
+    let distance = if speed >= n {
+        speed * n - triangle(n - 1)
+    } else {
+        triangle(speed)
+    };
+    vx.signum() * distance
+}
+
+fn y_after(vy: isize, n: isize) -> isize {
+    vy * n - triangle(n - 1)
+}
+
+// highest point up to and including step `n`
+fn highest_until(vy: isize, n: isize) -> isize {
+    if vy <= 0 {
+        0
+    } else {
+        y_after(vy, n.min(vy))
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+struct Physics {
+    // taken off vy every step
+    gravity: isize,
+    // speed lost towards zero every step, per axis
+    drag: [isize; 3],
+    // added to the position every step, per axis
+    wind: [isize; 3],
+    // a probe still flying after this many steps counts as a miss
+    max_steps: usize,
+}
+
+impl Default for Physics {
+    fn default() -> Self {
+        Self {
+            gravity: 1,
+            drag: [1, 0, 1],
+            wind: [0; 3],
+            max_steps: 100_000,
+        }
+    }
+}
+
+impl Physics {
+    fn acceleration(&self, axis: usize) -> isize {
+        if axis == 1 {
+            -self.gravity
+        } else {
+            0
+        }
+    }
+}
+
+// a cuboid, with an unbounded z range in 2D
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+struct Target {
+    ranges: [Interval; 3],
+}
+
+impl Target {
+    fn new<XRange: RangeBounds<isize>, YRange: RangeBounds<isize>>(
+        tx: &XRange,
+        ty: &YRange,
+    ) -> Self {
+        Self {
+            ranges: [
+                Interval::from_range(tx),
+                Interval::from_range(ty),
+                Interval::from_range(&(..)),
+            ],
+        }
+    }
+
+    fn with_z<ZRange: RangeBounds<isize>>(mut self, tz: &ZRange) -> Self {
+        self.ranges[2] = Interval::from_range(tz);
+        self
+    }
+
+    fn is_3d(&self) -> bool {
+        self.ranges[2] != Interval::from_range(&(..))
+    }
+
+    fn contains(&self, position: [isize; 3]) -> bool {
+        (0..3).all(|axis| self.ranges[axis].contains(position[axis]))
+    }
+
+    fn axes(&self) -> std::ops::Range<usize> {
+        if self.is_3d() {
+            0..3
+        } else {
+            0..2
+        }
+    }
+
+    // latest step at which `axis` can be inside the target, `None` if a probe can get in at any
+    // step: one that moves at least 1 a step is past the target after max_abs steps, and one
+    // at c != 0 after n steps under acceleration has n <= 2 * |c|
+    fn last_step(&self, physics: &Physics, axis: usize) -> Option<isize> {
+        let range = &self.ranges[axis];
+        let limit = range.max_abs();
+        let drag = physics.drag[axis];
+        if physics.acceleration(axis) != 0 {
+            (!range.contains(0)).then_some(2 * limit)
+        } else if drag > 0 {
+            (-limit..=limit)
+                .all(|v| !range.contains(rest_position(v, drag)))
+                .then_some(limit)
+        } else {
+            // moving by v + wind every step
+            (!range.contains(0)).then_some(limit)
+        }
+    }
+
+    // velocities searched by the `_with` functions, `None` unless they provably hold every hit:
+    // a probe slowed by drag is brought back by wind or falls through everything at a terminal
+    // speed, so drag only goes with a bounded axis without wind or acceleration. Otherwise
+    // u = v + wind and c = n * u + a * n * (n - 1) / 2 after n steps, so a hit at c != 0 has
+    // |u| <= |c| * (1 + |a|) and a hit at 0 has |u| <= |a| * (n - 1) / 2
+    fn search_box(&self, physics: &Physics) -> Option<[RangeInclusive<isize>; 3]> {
+        for axis in self.axes() {
+            let drag = physics.drag[axis];
+            let still = physics.wind[axis] == 0 && physics.acceleration(axis) == 0;
+            if !self.ranges[axis].is_bounded() || drag < 0 || (drag > 0 && !still) {
+                return None;
+            }
+        }
+        let steps = self
+            .axes()
+            .filter_map(|axis| self.last_step(physics, axis))
+            .min();
+        let mut limits = [0; 3];
+        for axis in self.axes() {
+            let range = &self.ranges[axis];
+            let acceleration = physics.acceleration(axis).abs();
+            let mut limit = range.max_abs() * (1 + acceleration);
+            if acceleration != 0 && range.contains(0) {
+                limit = limit.max(acceleration * steps? / 2);
+            }
+            limits[axis] = limit + physics.wind[axis].abs();
+        }
+        Some(limits.map(|limit| -limit..=limit))
+    }
+}
+
+// where a probe launched at `v` comes to rest under drag alone
+fn rest_position(v: isize, drag: isize) -> isize {
+    let speed = v.abs();
+    let moves = (speed + drag - 1) / drag;
+    v.signum() * (moves * speed - drag * triangle(moves - 1))
+}
+
+fn toward_zero(v: isize, drag: isize) -> isize {
+    if v > 0 {
+        (v - drag).max(0)
+    } else {
+        (v + drag).min(0)
+    }
+}
+
+// false once the axis has left `range` and nothing can bring it back: the velocity then stays
+// on the same side of zero and only moves towards the acceleration
+fn can_return(p: isize, v: isize, acceleration: isize, wind: isize, range: &Interval) -> bool {
+    let below = range.min.is_some_and(|min| p < min)
+        && v <= 0
+        && acceleration <= 0
+        && v.max(acceleration) + wind <= 0;
+    let above = range.max.is_some_and(|max| p > max)
+        && v >= 0
+        && acceleration >= 0
+        && v.min(acceleration) + wind >= 0;
+    !range.is_empty() && !below && !above
+}
+
+// positions after each step, by default up to and including the first one inside the target
+struct Flight<'a> {
+    physics: &'a Physics,
+    target: &'a Target,
+    position: [isize; 3],
+    velocity: [isize; 3],
+    steps: usize,
+    reached_target: bool,
+    stop_at_target: bool,
+}
+
+impl<'a> Flight<'a> {
+    fn new(physics: &'a Physics, velocity: [isize; 3], target: &'a Target) -> Self {
+        Self {
+            physics,
+            target,
+            position: [0; 3],
+            velocity,
+            steps: 0,
+            reached_target: false,
+            stop_at_target: true,
         }
     }
+
+    // carry on through the target until the probe can't return
+    fn past_target(mut self) -> Self {
+        self.stop_at_target = false;
+        self
+    }
+}
+
+impl Iterator for Flight<'_> {
+    type Item = [isize; 3];
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let physics = self.physics;
+        let flying = (0..3).all(|axis| {
+            can_return(
+                self.position[axis],
+                self.velocity[axis],
+                physics.acceleration(axis),
+                physics.wind[axis],
+                &self.target.ranges[axis],
+            )
+        });
+        if (self.reached_target && self.stop_at_target)
+            || self.steps == physics.max_steps
+            || !flying
+        {
+            return None;
+        }
+        for axis in 0..3 {
+            self.position[axis] += self.velocity[axis] + physics.wind[axis];
+            self.velocity[axis] =
+                toward_zero(self.velocity[axis], physics.drag[axis]) + physics.acceleration(axis);
+        }
+        self.steps += 1;
+        self.reached_target |= self.target.contains(self.position);
+        Some(self.position)
+    }
+}
+
+fn simulate_with(physics: &Physics, velocity: [isize; 3], target: &Target) -> Status {
+    let mut flight = Flight::new(physics, velocity, target);
+    let highest = flight
+        .by_ref()
+        .map(|position| position[1])
+        .fold(0, isize::max);
     Status {
         highest,
-        reached_target,
+        reached_target: flight.reached_target,
     }
 }
 
-fn find_highest<XRange: RangeBounds<isize>, YRange: RangeBounds<isize>>(
+#[cfg(test)]
+fn simulate<XRange: RangeBounds<isize>, YRange: RangeBounds<isize>>(
+    vx: isize,
+    vy: isize,
     tx: &XRange,
     ty: &YRange,
-) -> isize {
-    let max_x = *unwrap_bound(tx.end_bound());
-    let mut max_highest = 0;
-    for vx in 0..=max_x {
-        for vy in 0..1000 {
-            if let Status {
-                highest,
-                reached_target: true,
-            } = simulate(vx, vy, tx, ty)
+) -> Status {
+    simulate_with(&Physics::default(), [vx, vy, 0], &Target::new(tx, ty))
+}
+
+// `None` if `Target::search_box` can't bound the hits
+fn search_velocities(
+    physics: &Physics,
+    target: &Target,
+) -> Option<impl Iterator<Item = [isize; 3]>> {
+    let [xs, ys, zs] = target.search_box(physics)?;
+    Some(xs.flat_map(move |vx| {
+        let zs = zs.clone();
+        ys.clone()
+            .flat_map(move |vy| zs.clone().map(move |vz| [vx, vy, vz]))
+    }))
+}
+
+// every hitting velocity with its status, `None` if the search can't be bounded
+fn hits_with(physics: &Physics, target: &Target) -> Option<Vec<([isize; 3], Status)>> {
+    Some(
+        search_velocities(physics, target)?
+            .map(|velocity| (velocity, simulate_with(physics, velocity, target)))
+            .filter(|(_, status)| status.reached_target)
+            .collect(),
+    )
+}
+
+fn find_highest_with(physics: &Physics, target: &Target) -> Option<isize> {
+    Some(
+        hits_with(physics, target)?
+            .iter()
+            .map(|(_, status)| status.highest)
+            .max()
+            .unwrap_or(0),
+    )
+}
+
+fn count_within_with(physics: &Physics, target: &Target) -> Option<usize> {
+    Some(hits_with(physics, target)?.len())
+}
+
+// every hit with its details, `None` if the search can't be bounded
+fn hit_list_with(physics: &Physics, target: &Target) -> Option<Vec<Hit>> {
+    Some(
+        search_velocities(physics, target)?
+            .filter_map(|velocity| Hit::simulate(physics, velocity, target))
+            .collect(),
+    )
+}
+
+// a grid of `scale` x `scale` cells around the launcher, the target and some trajectories
+struct Canvas {
+    left: isize,
+    top: isize,
+    width: usize,
+    height: usize,
+    scale: isize,
+    visits: Vec<usize>,
+    target: Vec<bool>,
+}
+
+impl Canvas {
+    // `None` if the target is unbounded in x or y
+    fn new(target: &Target, trajectories: &[Vec<[isize; 3]>], scale: isize) -> Option<Self> {
+        assert!(scale > 0, "scale must be positive");
+        let [tx, ty, _] = target.ranges;
+        let bounds = |range: Interval, axis: usize| {
+            let (min, max) = (range.min?, range.max?);
+            let coords = trajectories.iter().flatten().map(|position| position[axis]);
+            let min = coords.clone().chain([0, min]).min().unwrap();
+            let max = coords.chain([0, max]).max().unwrap();
+            Some((min.div_euclid(scale), max.div_euclid(scale)))
+        };
+        let (left, right) = bounds(tx, 0)?;
+        let (bottom, top) = bounds(ty, 1)?;
+        let width = (right - left + 1) as usize;
+        let height = (top - bottom + 1) as usize;
+        let mut canvas = Self {
+            left,
+            top,
+            width,
+            height,
+            scale,
+            visits: vec![0; width * height],
+            target: vec![false; width * height],
+        };
+        for y in ty.min?..=ty.max? {
+            for x in tx.min?..=tx.max? {
+                let cell = canvas.cell(x, y);
+                canvas.target[cell] = true;
+            }
+        }
+        for position in trajectories.iter().flatten() {
+            let cell = canvas.cell(position[0], position[1]);
+            canvas.visits[cell] += 1;
+        }
+        Some(canvas)
+    }
+
+    fn cell(&self, x: isize, y: isize) -> usize {
+        let row = (self.top - y.div_euclid(self.scale)) as usize;
+        let column = (x.div_euclid(self.scale) - self.left) as usize;
+        row * self.width + column
+    }
+
+    fn max_visits(&self) -> usize {
+        self.visits.iter().copied().max().unwrap_or(0).max(1)
+    }
+
+    // the puzzle's diagrams, or visit counts from 1 to 9 relative to the busiest cell
+    fn to_text(&self, heatmap: bool) -> String {
+        let launcher = self.cell(0, 0);
+        let max = self.max_visits();
+        let mut text = String::new();
+        for row in 0..self.height {
+            for cell in row * self.width..(row + 1) * self.width {
+                text.push(match self.visits[cell] {
+                    _ if cell == launcher => 'S',
+                    0 if self.target[cell] => 'T',
+                    0 => '.',
+                    visits if heatmap => {
+                        char::from_digit((1 + (visits - 1) * 9 / max) as u32, 10).unwrap()
+                    }
+                    _ => '#',
+                });
+            }
+            text.push('\n');
+        }
+        text
+    }
+
+    // binary greyscale, darker is busier in a heatmap
+    fn to_pgm(&self, heatmap: bool) -> Vec<u8> {
+        let launcher = self.cell(0, 0);
+        let max = self.max_visits();
+        let mut pgm = format!("P5\n{} {}\n255\n", self.width, self.height).into_bytes();
+        pgm.extend((0..self.visits.len()).map(|cell| match self.visits[cell] {
+            _ if cell == launcher => 0,
+            0 if self.target[cell] => 192,
+            0 => 255,
+            visits if heatmap => (224 - (visits * 224 / max)) as u8,
+            _ => 0,
+        }));
+        pgm
+    }
+}
+
+// Works per step count n: x after n steps is monotone in vx and y after n steps is linear in vy,
+// so both give an interval of velocities. Every hit happens at a bounded n unless the target
+// is unbounded or a probe can stay inside it (x at rest in the target and 0 reachable in y).
+//
+// A hit with y = c != 0 after n steps means n * (2 * vy - n + 1) = 2 * c so n <= 2 * |c|.
+// A hit while still moving in x (n < |vx|) means |x| >= n * (n + 1) / 2.
+struct Solver {
+    tx: Interval,
+    ty: Interval,
+}
+
+impl Solver {
+    fn new<XRange: RangeBounds<isize>, YRange: RangeBounds<isize>>(
+        tx: &XRange,
+        ty: &YRange,
+    ) -> Self {
+        Self {
+            tx: Interval::from_range(tx),
+            ty: Interval::from_range(ty),
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.tx.is_empty() || self.ty.is_empty()
+    }
+
+    // some vx comes to rest inside the target, so x fits at every later step
+    fn can_rest_in_target(&self) -> bool {
+        let limit = self.tx.max_abs() + 1;
+        (-limit..=limit).any(|vx| self.tx.contains(x_after(vx, vx.abs())))
+    }
+
+    fn is_infinite(&self) -> bool {
+        !self.is_empty()
+            && (!self.tx.is_bounded()
+                || !self.ty.is_bounded()
+                || (self.ty.contains(0) && self.can_rest_in_target()))
+    }
+
+    fn is_apex_unbounded(&self) -> bool {
+        !self.is_empty()
+            && (self.ty.max.is_none()
+                || (self.can_rest_in_target() && (self.ty.contains(0) || self.ty.min.is_none())))
+    }
+
+    // largest step count a hit can need, `None` if unbounded
+    fn max_steps(&self) -> Option<isize> {
+        let by_y = (self.ty.is_bounded() && !self.ty.contains(0)).then(|| 2 * self.ty.max_abs());
+        let by_x = (!self.can_rest_in_target()).then(|| {
+            let mut n = 0;
+            while triangle(n + 1) <= self.tx.max_abs() {
+                n += 1;
+            }
+            n
+        });
+        match (by_y, by_x) {
+            (Some(y), Some(x)) => Some(y.min(x)),
+            (steps, None) | (None, steps) => steps,
+        }
+    }
+
+    // velocities with x in the target after `n` steps, limited to |vx| <= `limit`
+    fn vx_interval(&self, n: isize, limit: isize) -> Option<(isize, isize)> {
+        let first = |pred: &dyn Fn(isize) -> bool| {
+            let (mut lo, mut hi) = (-limit, limit + 1);
+            while lo < hi {
+                let mid = lo + (hi - lo) / 2;
+                if pred(mid) {
+                    hi = mid;
+                } else {
+                    lo = mid + 1;
+                }
+            }
+            lo
+        };
+        let lo = first(&|vx| self.tx.min.is_none_or(|min| x_after(vx, n) >= min));
+        let hi = first(&|vx| self.tx.max.is_some_and(|max| x_after(vx, n) > max)) - 1;
+        (lo <= hi).then_some((lo, hi))
+    }
+
+    // velocities with y in the target after `n` steps, an unbounded bottom is cut at -1 or at
+    // the top, whichever is lower, as all lower velocities behave the same
+    fn vy_interval(&self, n: isize) -> Option<(isize, isize)> {
+        let offset = triangle(n - 1);
+        let hi = (self.ty.max? + offset).div_euclid(n);
+        let lo = match self.ty.min {
+            Some(min) => (min + offset + n - 1).div_euclid(n),
+            None => hi.min(-1),
+        };
+        (lo <= hi).then_some((lo, hi))
+    }
+
+    // hitting velocities with the first step inside the target, a representative subset if
+    // there are infinitely many
+    fn hits(&self) -> BTreeMap<(isize, isize), isize> {
+        let mut hits = BTreeMap::new();
+        let max_steps = match self.max_steps() {
+            Some(max_steps) if !self.is_empty() => max_steps,
+            _ => return hits,
+        };
+        let limit = self.tx.max_abs() + 1;
+        for n in 1..=max_steps {
+            if let (Some((vx_lo, vx_hi)), Some((vy_lo, vy_hi))) =
+                (self.vx_interval(n, limit), self.vy_interval(n))
             {
-                if max_highest < highest {
-                    max_highest = highest;
+                for vx in vx_lo..=vx_hi {
+                    for vy in vy_lo..=vy_hi {
+                        hits.entry((vx, vy)).or_insert(n);
+                    }
                 }
             }
         }
+        hits
+    }
+
+    // `None` if infinitely many velocities hit
+    fn count(&self) -> Option<usize> {
+        if self.is_infinite() {
+            None
+        } else {
+            Some(self.hits().len())
+        }
+    }
+
+    // highest point reached before getting into the target (like `simulate`), 0 if nothing
+    // hits and `None` if unbounded
+    fn highest(&self) -> Option<isize> {
+        if self.is_apex_unbounded() {
+            return None;
+        }
+        Some(
+            self.hits()
+                .iter()
+                .map(|(&(_, vy), &n)| highest_until(vy, n))
+                .max()
+                .unwrap_or(0),
+        )
     }
-    max_highest
 }
 
+#[cfg(test)]
+fn find_highest<XRange: RangeBounds<isize>, YRange: RangeBounds<isize>>(
+    tx: &XRange,
+    ty: &YRange,
+) -> isize {
+    Solver::new(tx, ty).highest().expect("apex is unbounded")
+}
+
+#[cfg(test)]
 fn count_within<XRange: RangeBounds<isize>, YRange: RangeBounds<isize>>(
     tx: &XRange,
     ty: &YRange,
 ) -> usize {
-    let max_x = *unwrap_bound(tx.end_bound());
-    let min_y = *unwrap_bound(ty.start_bound());
-    let mut count = 0;
-    for vx in 0..=max_x {
-        for vy in min_y..1000 {
-            if simulate(vx, vy, tx, ty).reached_target {
-                count += 1;
+    Solver::new(tx, ty)
+        .count()
+        .expect("infinitely many velocities hit")
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+struct Hit {
+    vx: isize,
+    vy: isize,
+    vz: isize,
+    first_step: isize,
+    steps_inside: Vec<isize>,
+    // over the whole flight, unless nothing slows y down
+    apex: isize,
+    // no horizontal speed left when entering the target
+    drops_straight_in: bool,
+}
+
+impl Hit {
+    // from a single flight carried on past the target, `None` if the probe misses
+    fn simulate(physics: &Physics, velocity: [isize; 3], target: &Target) -> Option<Self> {
+        let mut flight = Flight::new(physics, velocity, target).past_target();
+        let mut first_step = None;
+        let mut steps_inside = Vec::new();
+        let mut apex = 0;
+        let mut drops_straight_in = false;
+        while let Some(position) = flight.next() {
+            apex = apex.max(position[1]);
+            if target.contains(position) {
+                let step = flight.steps as isize;
+                if first_step.is_none() {
+                    first_step = Some(step);
+                    drops_straight_in = flight.velocity[0] == 0 && flight.velocity[2] == 0;
+                }
+                steps_inside.push(step);
             }
         }
+        // the flight ends once the target is out of reach, which can be on the way up
+        let (mut y, mut vy) = (flight.position[1], flight.velocity[1]);
+        while physics.gravity > 0 && vy + physics.wind[1] > 0 {
+            y += vy + physics.wind[1];
+            vy = toward_zero(vy, physics.drag[1]) - physics.gravity;
+            apex = apex.max(y);
+        }
+        let [vx, vy, vz] = velocity;
+        Some(Self {
+            vx,
+            vy,
+            vz,
+            first_step: first_step?,
+            steps_inside,
+            apex,
+            drops_straight_in,
+        })
+    }
+
+    fn time_inside(&self) -> usize {
+        self.steps_inside.len()
+    }
+
+    fn speed(&self) -> isize {
+        self.vx.abs() + self.vy.abs() + self.vz.abs()
+    }
+}
+
+// vz only when there is one
+impl std::fmt::Display for Hit {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let steps: Vec<String> = self.steps_inside.iter().map(isize::to_string).collect();
+        write!(f, "{},{}", self.vx, self.vy)?;
+        if self.vz != 0 {
+            write!(f, ",{}", self.vz)?;
+        }
+        write!(
+            f,
+            " first step {}, inside at {}, apex {}",
+            self.first_step,
+            steps.join(","),
+            self.apex
+        )?;
+        if self.drops_straight_in {
+            f.write_str(", drops straight in")?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum SortKey {
+    Velocity,
+    FirstStep,
+    TimeInside,
+    Apex,
+    Speed,
+}
+
+impl SortKey {
+    fn parse(name: &str) -> Option<Self> {
+        match name {
+            "velocity" => Some(Self::Velocity),
+            "first" => Some(Self::FirstStep),
+            "inside" => Some(Self::TimeInside),
+            "apex" => Some(Self::Apex),
+            "speed" => Some(Self::Speed),
+            _ => None,
+        }
+    }
+}
+
+// stable, so ties stay ordered by velocity
+fn sort_hits(hits: &mut [Hit], key: SortKey, reverse: bool) {
+    hits.sort_by(|a, b| {
+        let ordering = match key {
+            SortKey::Velocity => (a.vx, a.vy, a.vz).cmp(&(b.vx, b.vy, b.vz)),
+            SortKey::FirstStep => a.first_step.cmp(&b.first_step),
+            SortKey::TimeInside => a.time_inside().cmp(&b.time_inside()),
+            SortKey::Apex => a.apex.cmp(&b.apex),
+            SortKey::Speed => a.speed().cmp(&b.speed()),
+        };
+        if reverse {
+            ordering.reverse()
+        } else {
+            ordering
+        }
+    });
+}
+
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+struct HitFilter {
+    drops_straight_in: Option<bool>,
+    min_time_inside: usize,
+    max_first_step: Option<isize>,
+    max_apex: Option<isize>,
+    max_speed: Option<isize>,
+}
+
+impl HitFilter {
+    fn matches(&self, hit: &Hit) -> bool {
+        self.drops_straight_in
+            .is_none_or(|straight| hit.drops_straight_in == straight)
+            && hit.time_inside() >= self.min_time_inside
+            && self.max_first_step.is_none_or(|max| hit.first_step <= max)
+            && self.max_apex.is_none_or(|max| hit.apex <= max)
+            && self.max_speed.is_none_or(|max| hit.speed() <= max)
+    }
+}
+
+// the velocity of the hit passing `filter` with the largest `objective`, the first one in
+// velocity order on ties. `None` if nothing passes or the search can't be bounded
+fn optimize<K: Ord>(
+    physics: &Physics,
+    target: &Target,
+    objective: impl Fn(&Hit) -> K,
+    filter: &HitFilter,
+) -> Option<([isize; 3], Status)> {
+    let mut best: Option<(K, [isize; 3])> = None;
+    for velocity in search_velocities(physics, target)? {
+        let hit = match Hit::simulate(physics, velocity, target) {
+            Some(hit) if filter.matches(&hit) => hit,
+            _ => continue,
+        };
+        let score = objective(&hit);
+        if best.as_ref().is_none_or(|(best, _)| score > *best) {
+            best = Some((score, velocity));
+        }
+    }
+    let (_, velocity) = best?;
+    Some((velocity, simulate_with(physics, velocity, target)))
+}
+
+impl Solver {
+    fn hit(&self, vx: isize, vy: isize, first_step: isize) -> Hit {
+        // y only falls once past the apex, so stop when it drops below the target
+        let steps_inside = (first_step..)
+            .take_while(|&n| n <= vy || self.ty.min.is_none_or(|min| y_after(vy, n) >= min))
+            .filter(|&n| self.tx.contains(x_after(vx, n)) && self.ty.contains(y_after(vy, n)))
+            .collect();
+        Hit {
+            vx,
+            vy,
+            vz: 0,
+            first_step,
+            steps_inside,
+            apex: triangle(vy.max(0)),
+            drops_straight_in: vx.abs() <= first_step,
+        }
+    }
+
+    // every hitting velocity in (vx, vy) order, `None` if there are infinitely many
+    fn hit_list(&self) -> Option<Vec<Hit>> {
+        if self.is_infinite() {
+            return None;
+        }
+        Some(
+            self.hits()
+                .into_iter()
+                .map(|((vx, vy), n)| self.hit(vx, vy, n))
+                .collect(),
+        )
+    }
+}
+
+static TARGET: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"x=(-?\d+)\.\.(-?\d+), *y=(-?\d+)\.\.(-?\d+)").unwrap());
+
+// "target area: x=20..30, y=-10..-5"
+fn parse_target(s: &str) -> Option<(RangeInclusive<isize>, RangeInclusive<isize>)> {
+    let captures = TARGET.captures(s)?;
+    let n = |i: usize| captures[i].parse::<isize>().ok();
+    Some((n(1)?..=n(2)?, n(3)?..=n(4)?))
+}
+
+fn filter_from_args(args: &[String]) -> HitFilter {
+    let flag = |name: &str| args.iter().any(|arg| arg == name);
+    let option = |prefix: &str| args.iter().find_map(|arg| arg.strip_prefix(prefix));
+    HitFilter {
+        drops_straight_in: if flag("--straight-in") {
+            Some(true)
+        } else if flag("--no-straight-in") {
+            Some(false)
+        } else {
+            None
+        },
+        min_time_inside: option("--min-inside=").map_or(0, |n| n.parse().unwrap()),
+        max_first_step: option("--max-first=").map(|n| n.parse().unwrap()),
+        max_apex: option("--max-apex=").map(|n| n.parse().unwrap()),
+        max_speed: option("--max-speed=").map(|n| n.parse().unwrap()),
+    }
+}
+
+fn print_hits(mut hits: Vec<Hit>, args: &[String]) {
+    let flag = |name: &str| args.iter().any(|arg| arg == name);
+    let option = |prefix: &str| args.iter().find_map(|arg| arg.strip_prefix(prefix));
+    let filter = filter_from_args(args);
+    hits.retain(|hit| filter.matches(hit));
+    let key = option("--sort=").map_or(SortKey::Velocity, |key| SortKey::parse(key).unwrap());
+    sort_hits(&mut hits, key, flag("--reverse"));
+    for hit in hits {
+        println!("{hit}");
+    }
+}
+
+fn print_optimized(physics: &Physics, target: &Target, objective: &str, args: &[String]) {
+    if target.search_box(physics).is_none() {
+        unbounded();
+    }
+    let filter = filter_from_args(args);
+    let best = match objective {
+        "highest" => optimize(physics, target, |hit| hit.apex, &filter),
+        "earliest" => optimize(physics, target, |hit| Reverse(hit.first_step), &filter),
+        "latest" => optimize(physics, target, |hit| hit.first_step, &filter),
+        "longest" => optimize(physics, target, Hit::time_inside, &filter),
+        "slowest" => optimize(physics, target, |hit| Reverse(hit.speed()), &filter),
+        _ => {
+            eprintln!("unknown objective {objective:?}");
+            std::process::exit(1);
+        }
+    };
+    match best {
+        Some(([vx, vy, vz], status)) if target.is_3d() => {
+            println!("{vx},{vy},{vz} highest {}", status.highest)
+        }
+        Some(([vx, vy, _], status)) => println!("{vx},{vy} highest {}", status.highest),
+        None => println!("no launch hits the target"),
+    }
+}
+
+fn parse_axes(s: &str) -> [isize; 3] {
+    let axes: Vec<isize> = s.split(',').map(|n| n.parse().unwrap()).collect();
+    axes.try_into().expect("expected X,Y,Z")
+}
+
+// custom physics or a z range from the arguments, `None` for the default 2D rules
+fn physics_from_args(args: &[String]) -> Option<(Physics, Option<RangeInclusive<isize>>)> {
+    let option = |prefix: &str| args.iter().find_map(|arg| arg.strip_prefix(prefix));
+    let defaults = Physics::default();
+    let physics = Physics {
+        gravity: option("--gravity=").map_or(defaults.gravity, |n| n.parse().unwrap()),
+        drag: option("--drag=").map_or(defaults.drag, parse_axes),
+        wind: option("--wind=").map_or(defaults.wind, parse_axes),
+        ..defaults
+    };
+    let tz = option("--z=").map(|range| {
+        let (min, max) = range.split_once("..").expect("expected MIN..MAX");
+        min.parse().unwrap()..=max.parse().unwrap()
+    });
+    (physics != defaults || tz.is_some()).then_some((physics, tz))
+}
+
+fn unbounded() -> ! {
+    eprintln!("can't bound the launch velocities for this target and physics");
+    std::process::exit(1)
+}
+
+fn draw(physics: &Physics, target: &Target, args: &[String]) {
+    let flag = |name: &str| args.iter().any(|arg| arg == name);
+    let option = |prefix: &str| args.iter().find_map(|arg| arg.strip_prefix(prefix));
+    let scale: isize = option("--scale=").map_or(1, |n| n.parse().unwrap());
+    if scale < 1 {
+        eprintln!("--scale must be at least 1");
+        std::process::exit(1);
+    }
+    let trajectories: Vec<Vec<[isize; 3]>> = match option("--draw=") {
+        Some(velocity) => {
+            let mut velocity: Vec<isize> =
+                velocity.split(',').map(|n| n.parse().unwrap()).collect();
+            velocity.resize(3, 0);
+            let velocity = velocity.try_into().expect("expected VX,VY[,VZ]");
+            vec![Flight::new(physics, velocity, target).collect()]
+        }
+        None => hits_with(physics, target)
+            .unwrap_or_else(|| unbounded())
+            .into_iter()
+            .map(|(velocity, _)| Flight::new(physics, velocity, target).collect())
+            .collect(),
+    };
+    let canvas = match Canvas::new(target, &trajectories, scale) {
+        Some(canvas) => canvas,
+        None => {
+            eprintln!("can't draw a target that is unbounded in x or y");
+            std::process::exit(1);
+        }
+    };
+    let heatmap = flag("--heatmap");
+    if flag("--pgm") {
+        io::stdout().write_all(&canvas.to_pgm(heatmap)).unwrap();
+    } else {
+        print!("{}", canvas.to_text(heatmap));
     }
-    count
 }
 
+// usage: day17 --hits [--sort=velocity|first|inside|apex|speed] [--reverse] [FILTER] [PHYSICS]
+//              [TARGET]
+//        day17 --optimize=highest|earliest|latest|longest|slowest [FILTER] [PHYSICS] [TARGET]
+//        day17 [--draw=VX,VY[,VZ]|--heatmap] [--pgm] [--scale=N] [PHYSICS] [TARGET]
+//        day17 [PHYSICS] [TARGET]
+// FILTER is any of --straight-in --no-straight-in --min-inside=N --max-first=N --max-apex=N
+// --max-speed=N. PHYSICS is any of --gravity=N --drag=X,Y,Z --wind=X,Y,Z --z=MIN..MAX, drag is only supported
+// on axes without wind or gravity. TARGET is the puzzle input, e.g.
+// "target area: x=20..30, y=-10..-5"
 fn main() {
-    println!("Part 1: {}", find_highest(&(32..=65), &(-225..=-177)));
-    println!("Part 2: {}", count_within(&(32..=65), &(-225..=-177)));
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let (tx, ty) = match args.iter().find(|arg| !arg.starts_with("--")) {
+        Some(target) => parse_target(target).expect("invalid target"),
+        None => (32..=65, -225..=-177),
+    };
+    let custom = physics_from_args(&args);
+    let (physics, tz) = custom.clone().unwrap_or_default();
+    let mut target = Target::new(&tx, &ty);
+    if let Some(tz) = tz {
+        target = target.with_z(&tz);
+    }
+    if args
+        .iter()
+        .any(|arg| arg.starts_with("--draw=") || arg == "--heatmap")
+    {
+        draw(&physics, &target, &args);
+        return;
+    }
+    if let Some(objective) = args.iter().find_map(|arg| arg.strip_prefix("--optimize=")) {
+        print_optimized(&physics, &target, objective, &args);
+        return;
+    }
+    if args.iter().any(|arg| arg == "--hits") {
+        let hits = match custom {
+            Some(_) => hit_list_with(&physics, &target),
+            None => Solver::new(&tx, &ty).hit_list(),
+        };
+        print_hits(hits.unwrap_or_else(|| unbounded()), &args);
+        return;
+    }
+    if custom.is_some() {
+        let highest = find_highest_with(&physics, &target).unwrap_or_else(|| unbounded());
+        println!("Part 1: {highest}");
+        let count = count_within_with(&physics, &target).unwrap_or_else(|| unbounded());
+        println!("Part 2: {count}");
+        return;
+    }
+    let solver = Solver::new(&tx, &ty);
+    println!(
+        "Part 1: {}",
+        solver
+            .highest()
+            .map_or_else(|| "unbounded".to_string(), |highest| highest.to_string())
+    );
+    println!(
+        "Part 2: {}",
+        solver
+            .count()
+            .map_or_else(|| "infinite".to_string(), |count| count.to_string())
+    );
 }
 
 #[cfg(test)]
@@ -140,4 +1063,477 @@ mod tests {
     fn count_within_sample() {
         assert_eq!(count_within(&(20..=30), &(-10..=-5)), 112);
     }
+
+    // every simulated hit with |vx|, |vy| <= `window`
+    fn simulated_hits<XRange: RangeBounds<isize>, YRange: RangeBounds<isize>>(
+        tx: &XRange,
+        ty: &YRange,
+        window: isize,
+    ) -> BTreeMap<(isize, isize), isize> {
+        let mut hits = BTreeMap::new();
+        for vx in -window..=window {
+            for vy in -window..=window {
+                let status = simulate(vx, vy, tx, ty);
+                if status.reached_target {
+                    hits.insert((vx, vy), status.highest);
+                }
+            }
+        }
+        hits
+    }
+
+    fn assert_matches_simulation<XRange: RangeBounds<isize>, YRange: RangeBounds<isize>>(
+        tx: &XRange,
+        ty: &YRange,
+    ) {
+        let solver = Solver::new(tx, ty);
+        let simulated = simulated_hits(tx, ty, 100);
+        let solved: BTreeMap<(isize, isize), isize> = solver
+            .hits()
+            .iter()
+            .map(|(&(vx, vy), &n)| ((vx, vy), highest_until(vy, n)))
+            .collect();
+        assert_eq!(solved, simulated);
+        assert_eq!(solver.count(), Some(simulated.len()));
+        assert_eq!(
+            solver.highest(),
+            Some(simulated.values().copied().max().unwrap_or(0))
+        );
+    }
+
+    #[test]
+    fn test_solver_quadrants() {
+        // below right, below left, above right, above left
+        assert_matches_simulation(&(20..=30), &(-10..=-5));
+        assert_matches_simulation(&(-30..=-20), &(-10..=-5));
+        assert_matches_simulation(&(20..=30), &(5..=10));
+        assert_matches_simulation(&(-30..=-20), &(5..=10));
+        // straddling the launcher in x or y
+        assert_matches_simulation(&(-5..=5), &(-10..=-5));
+        assert_matches_simulation(&(-5..=5), &(5..=10));
+        assert_matches_simulation(&(22..=27), &(-5..=5));
+        assert_matches_simulation(&(-27..=-22), &(-5..=5));
+        // nothing to hit
+        assert_matches_simulation(&(100..=100), &(-1..=-1));
+    }
+
+    #[test]
+    fn test_solver_range_bounds() {
+        assert_matches_simulation(&(20..31), &(-10..-4));
+        assert_eq!(count_within(&(20..31), &(-10..-4)), 112);
+        assert_matches_simulation(&(19..30), &(-10..=-6));
+
+        // unbounded targets: infinitely many hits but the apex may still be bounded
+        let solver = Solver::new(&(20..), &(-10..=-5));
+        assert_eq!(solver.count(), None);
+        assert_eq!(solver.highest(), Some(45));
+        let solver = Solver::new(&(20..=30), &(..=-5));
+        assert_eq!(solver.count(), None);
+        assert_eq!(solver.highest(), None);
+        let solver = Solver::new(&(..), &(-10..=-5));
+        assert_eq!(solver.count(), None);
+        assert_eq!(solver.highest(), Some(45));
+
+        // a probe can come to rest at x = 21 while y passes 0
+        let solver = Solver::new(&(20..=30), &(-5..=5));
+        assert_eq!(solver.count(), None);
+        assert_eq!(solver.highest(), None);
+    }
+
+    #[test]
+    fn test_hit_list() {
+        let solver = Solver::new(&(20..=30), &(-10..=-5));
+        let hits = solver.hit_list().unwrap();
+        assert_eq!(hits.len(), 112);
+        let highest = hits.iter().find(|hit| (hit.vx, hit.vy) == (6, 9)).unwrap();
+        assert_eq!(
+            highest,
+            &Hit {
+                vx: 6,
+                vy: 9,
+                vz: 0,
+                first_step: 20,
+                steps_inside: vec![20],
+                apex: 45,
+                drops_straight_in: true,
+            }
+        );
+        // entering while still moving right, then drifting through
+        let hit = hits.iter().find(|hit| (hit.vx, hit.vy) == (8, -1)).unwrap();
+        assert_eq!((hit.first_step, hit.steps_inside.clone()), (3, vec![3, 4]));
+        assert!(!hit.drops_straight_in);
+
+        // steps inside agree with simulating step by step
+        for hit in &hits {
+            let (mut x, mut y, mut vx, mut vy) = (0, 0, hit.vx, hit.vy);
+            let mut inside = Vec::new();
+            for n in 1..=30 {
+                x += vx;
+                y += vy;
+                vx -= vx.signum();
+                vy -= 1;
+                if (20..=30).contains(&x) && (-10..=-5).contains(&y) {
+                    inside.push(n);
+                }
+            }
+            assert_eq!(hit.steps_inside, inside);
+        }
+    }
+
+    #[test]
+    fn test_sort_and_filter_hits() {
+        let mut hits = Solver::new(&(20..=30), &(-10..=-5)).hit_list().unwrap();
+        sort_hits(&mut hits, SortKey::TimeInside, true);
+        let longest = hits[0].time_inside();
+        assert!(hits.iter().all(|hit| hit.time_inside() <= longest));
+        sort_hits(&mut hits, SortKey::FirstStep, false);
+        assert_eq!((hits[0].vx, hits[0].first_step), (20, 1));
+
+        let filter = HitFilter {
+            drops_straight_in: Some(true),
+            max_apex: Some(10),
+            ..HitFilter::default()
+        };
+        let straight: Vec<(isize, isize)> = hits
+            .iter()
+            .filter(|hit| filter.matches(hit))
+            .map(|hit| (hit.vx, hit.vy))
+            .collect();
+        assert!(straight
+            .iter()
+            .all(|&(vx, vy)| (6..=7).contains(&vx) && vy <= 4));
+        assert!(straight.contains(&(6, 4)));
+    }
+
+    #[test]
+    fn test_parse_target() {
+        assert_eq!(
+            parse_target("target area: x=20..30, y=-10..-5"),
+            Some((20..=30, -10..=-5))
+        );
+        assert_eq!(parse_target("target area: x=20..30"), None);
+    }
+
+    #[test]
+    fn test_default_physics() {
+        let physics = Physics::default();
+        let target = Target::new(&(20..=30), &(-10..=-5));
+        assert_eq!(find_highest_with(&physics, &target), Some(45));
+        assert_eq!(count_within_with(&physics, &target), Some(112));
+        for (tx, ty) in [
+            (-30..=-20, -10..=-5),
+            (20..=30, 5..=10),
+            (-5..=5, -10..=-5),
+            (22..=27, -5..=5),
+        ] {
+            let target = Target::new(&tx, &ty);
+            let solver = Solver::new(&tx, &ty);
+            assert_eq!(find_highest_with(&physics, &target), solver.highest());
+            assert_eq!(count_within_with(&physics, &target), solver.count());
+        }
+        // a probe can come to rest at x = 21 while y passes 0
+        let target = Target::new(&(20..=30), &(-5..=5));
+        assert_eq!(count_within_with(&physics, &target), None);
+        assert_eq!(find_highest_with(&physics, &target), None);
+    }
+
+    #[test]
+    fn test_custom_physics() {
+        let target = Target::new(&(20..=30), &(-10..=-5));
+        // no drag: x moves by vx every step
+        let physics = Physics {
+            drag: [0; 3],
+            ..Physics::default()
+        };
+        assert_eq!(
+            simulate_with(&physics, [5, 0, 0], &target),
+            Status {
+                highest: 0,
+                reached_target: true
+            }
+        );
+        assert!(!simulate_with(&Physics::default(), [5, 0, 0], &target).reached_target);
+
+        // double gravity: up 4, 6, 6, 4, 0, then down to -6 on the seventh step
+        let physics = Physics {
+            gravity: 2,
+            ..Physics::default()
+        };
+        assert_eq!(
+            simulate_with(&physics, [6, 6, 0], &target),
+            Status {
+                highest: 12,
+                reached_target: true
+            }
+        );
+
+        // wind pushing left brings back a probe that overshoots to x = 36
+        let physics = Physics {
+            wind: [-3, 0, 0],
+            ..Physics::default()
+        };
+        let status = simulate_with(&physics, [11, 5, 0], &target);
+        assert!(status.reached_target);
+        assert_eq!(status.highest, 15);
+        // but it brings back probes launched arbitrarily fast, so the hits can't be bounded
+        assert_eq!(count_within_with(&physics, &target), None);
+        // as does drag on y, with a terminal speed of 1 a probe falls through every height
+        let physics = Physics {
+            drag: [1, 1, 1],
+            ..Physics::default()
+        };
+        assert_eq!(count_within_with(&physics, &target), None);
+    }
+
+    // every velocity within `window` that hits, by simulation
+    fn brute_force_hits(physics: &Physics, target: &Target, window: isize) -> Vec<[isize; 3]> {
+        let mut hits = Vec::new();
+        for vx in -window..=window {
+            for vy in -window..=window {
+                if simulate_with(physics, [vx, vy, 0], target).reached_target {
+                    hits.push([vx, vy, 0]);
+                }
+            }
+        }
+        hits
+    }
+
+    #[test]
+    fn test_search_box() {
+        let physics = |gravity, drag, wind| Physics {
+            gravity,
+            drag,
+            wind,
+            ..Physics::default()
+        };
+        for (physics, target) in [
+            (Physics::default(), Target::new(&(22..=27), &(-5..=5))),
+            (
+                physics(2, [0; 3], [-3, 2, 0]),
+                Target::new(&(-10..=10), &(5..=10)),
+            ),
+            (
+                physics(3, [0; 3], [0, 1, 0]),
+                Target::new(&(4..=9), &(-3..=3)),
+            ),
+            (
+                physics(1, [2, 0, 0], [0, -2, 0]),
+                Target::new(&(-12..=-3), &(-8..=-2)),
+            ),
+        ] {
+            let mut hits: Vec<[isize; 3]> = hits_with(&physics, &target)
+                .unwrap()
+                .into_iter()
+                .map(|(velocity, _)| velocity)
+                .collect();
+            hits.sort();
+            assert!(!hits.is_empty());
+            assert_eq!(hits, brute_force_hits(&physics, &target, 150));
+        }
+    }
+
+    #[test]
+    fn test_cuboid_target() {
+        let physics = Physics::default();
+        let flat = Target::new(&(20..=30), &(-10..=-5));
+        let cuboid = flat.with_z(&(20..=30));
+        assert_eq!(
+            simulate_with(&physics, [6, 9, 7], &cuboid),
+            Status {
+                highest: 45,
+                reached_target: true
+            }
+        );
+        assert!(!simulate_with(&physics, [6, 9, 0], &cuboid).reached_target);
+        // z behaves like x, so every hit pairs a vx and a vz that each hit on their own
+        assert_eq!(find_highest_with(&physics, &cuboid), Some(45));
+        let hits = hits_with(&physics, &cuboid).unwrap();
+        assert!(hits
+            .iter()
+            .all(|([vx, vy, _], _)| simulate(*vx, *vy, &(20..=30), &(-10..=-5)).reached_target));
+    }
+
+    fn draw_sample(vx: isize, vy: isize) -> String {
+        let physics = Physics::default();
+        let target = Target::new(&(20..=30), &(-10..=-5));
+        let trajectory: Vec<[isize; 3]> = Flight::new(&physics, [vx, vy, 0], &target).collect();
+        Canvas::new(&target, &[trajectory], 1)
+            .unwrap()
+            .to_text(false)
+    }
+
+    #[test]
+    fn test_draw() {
+        assert_eq!(
+            draw_sample(7, 2),
+            "\
+.............#....#............
+.......#..............#........
+...............................
+S........................#.....
+...............................
+...............................
+...........................#...
+...............................
+....................TTTTTTTTTTT
+....................TTTTTTTTTTT
+....................TTTTTTTT#TT
+....................TTTTTTTTTTT
+....................TTTTTTTTTTT
+....................TTTTTTTTTTT
+"
+        );
+        assert_eq!(
+            draw_sample(9, 0),
+            "\
+S........#.....................
+.................#.............
+...............................
+........................#......
+...............................
+....................TTTTTTTTTTT
+....................TTTTTTTTTT#
+....................TTTTTTTTTTT
+....................TTTTTTTTTTT
+....................TTTTTTTTTTT
+....................TTTTTTTTTTT
+"
+        );
+    }
+
+    #[test]
+    fn test_heatmap() {
+        let physics = Physics::default();
+        let target = Target::new(&(20..=30), &(-10..=-5));
+        let trajectories: Vec<Vec<[isize; 3]>> = hits_with(&physics, &target)
+            .unwrap()
+            .into_iter()
+            .map(|(velocity, _)| Flight::new(&physics, velocity, &target).collect())
+            .collect();
+        assert_eq!(trajectories.len(), 112);
+        let canvas = Canvas::new(&target, &trajectories, 5).unwrap();
+        // x from 0 to 30 and y from -10 to 45 in cells of 5
+        assert_eq!((canvas.width, canvas.height), (7, 12));
+        let text = canvas.to_text(true);
+        assert!(text.starts_with('.'));
+        assert!(text.contains('9'));
+        assert!(!text.contains('T'));
+
+        let pgm = canvas.to_pgm(true);
+        let header = b"P5\n7 12\n255\n";
+        assert_eq!(&pgm[..header.len()], header);
+        assert_eq!(pgm.len(), header.len() + 7 * 12);
+        assert!(pgm[header.len()..].contains(&0));
+
+        assert!(Canvas::new(&Target::new(&(20..), &(-10..=-5)), &trajectories, 1).is_none());
+        assert!(Canvas::new(&Target::new(&(20..=30), &(..=-5)), &[], 1).is_none());
+    }
+
+    #[test]
+    fn test_hit_list_with() {
+        // one flight per velocity gives the solver's hits, including the apex of probes that
+        // leave a target above the launcher on the way up
+        let physics = Physics::default();
+        for (tx, ty) in [
+            (20..=30, -10..=-5),
+            (-30..=-20, -10..=-5),
+            (20..=30, 5..=10),
+            (-5..=5, 5..=10),
+        ] {
+            assert_eq!(
+                hit_list_with(&physics, &Target::new(&tx, &ty)),
+                Solver::new(&tx, &ty).hit_list()
+            );
+        }
+        let cuboid = Target::new(&(20..=30), &(-10..=-5)).with_z(&(-3..=3));
+        let hits = hit_list_with(&physics, &cuboid).unwrap();
+        let hit = hits.iter().find(|hit| hit.vz == -2).unwrap();
+        assert!(hit
+            .to_string()
+            .starts_with(&format!("{},{},-2 first", hit.vx, hit.vy)));
+    }
+
+    #[test]
+    fn test_optimize() {
+        let physics = Physics::default();
+        let target = Target::new(&(20..=30), &(-10..=-5));
+        let free = HitFilter::default();
+        let velocity = |best: Option<([isize; 3], Status)>| best.map(|(velocity, _)| velocity);
+        assert_eq!(
+            optimize(&physics, &target, |hit| hit.apex, &free),
+            Some((
+                [6, 9, 0],
+                Status {
+                    highest: 45,
+                    reached_target: true
+                }
+            ))
+        );
+        assert_eq!(
+            velocity(optimize(
+                &physics,
+                &target,
+                |hit| Reverse(hit.first_step),
+                &free
+            )),
+            Some([20, -10, 0])
+        );
+        assert_eq!(
+            velocity(optimize(&physics, &target, |hit| hit.first_step, &free)),
+            Some([6, 9, 0])
+        );
+        assert_eq!(
+            velocity(optimize(
+                &physics,
+                &target,
+                |hit| Reverse(hit.speed()),
+                &free
+            )),
+            Some([6, 0, 0])
+        );
+        let hits = hit_list_with(&physics, &target).unwrap();
+        let [vx, vy, vz] = velocity(optimize(&physics, &target, Hit::time_inside, &free)).unwrap();
+        assert_eq!(
+            Hit::simulate(&physics, [vx, vy, vz], &target)
+                .unwrap()
+                .time_inside(),
+            hits.iter().map(Hit::time_inside).max().unwrap()
+        );
+
+        let low = HitFilter {
+            max_apex: Some(20),
+            ..HitFilter::default()
+        };
+        let (_, status) = optimize(&physics, &target, |hit| hit.apex, &low).unwrap();
+        assert_eq!(status.highest, 15);
+        let slow = HitFilter {
+            max_speed: Some(7),
+            ..HitFilter::default()
+        };
+        let [vx, vy, _] = velocity(optimize(&physics, &target, |hit| hit.apex, &slow)).unwrap();
+        assert_eq!(vx.abs() + vy.abs(), 7);
+
+        let impossible = HitFilter {
+            max_first_step: Some(0),
+            ..HitFilter::default()
+        };
+        assert_eq!(
+            optimize(&physics, &target, |hit| hit.apex, &impossible),
+            None
+        );
+        // any objective, here the lowest apex among hits staying inside at least twice
+        let twice = HitFilter {
+            min_time_inside: 2,
+            ..HitFilter::default()
+        };
+        let (velocity, status) =
+            optimize(&physics, &target, |hit| Reverse(hit.apex), &twice).unwrap();
+        assert!(status.reached_target);
+        assert!(
+            Hit::simulate(&physics, velocity, &target)
+                .unwrap()
+                .time_inside()
+                >= 2
+        );
+    }
 }
@@ -20,6 +20,87 @@ enum Token {
     Num(usize),
 }
 
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Syntax {
+    // exactly `[a,b]` with single digit numbers and nothing after it
+    Strict,
+    // skips commas and stops after the first complete pair
+    Lenient,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum ParseErrorKind {
+    Expected(&'static str),
+    LargeNumber,
+    TrailingInput,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+struct ParseError {
+    // in characters
+    offset: usize,
+    kind: ParseErrorKind,
+}
+
+impl std::fmt::Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.kind {
+            ParseErrorKind::Expected(expected) => write!(f, "expected {expected}")?,
+            ParseErrorKind::LargeNumber => f.write_str("regular number above 9")?,
+            ParseErrorKind::TrailingInput => f.write_str("input after the number")?,
+        }
+        write!(f, " at offset {}", self.offset)
+    }
+}
+
+struct StrictParser<'a> {
+    chars: std::iter::Peekable<std::iter::Enumerate<std::str::Chars<'a>>>,
+    len: usize,
+}
+
+impl StrictParser<'_> {
+    fn error(&mut self, kind: ParseErrorKind) -> ParseError {
+        let offset = self.chars.peek().map_or(self.len, |&(i, _)| i);
+        ParseError { offset, kind }
+    }
+
+    fn expect(&mut self, c: char, expected: &'static str) -> Result<(), ParseError> {
+        match self.chars.peek() {
+            Some(&(_, next)) if next == c => {
+                self.chars.next();
+                Ok(())
+            }
+            _ => Err(self.error(ParseErrorKind::Expected(expected))),
+        }
+    }
+
+    fn element(&mut self) -> Result<Pair, ParseError> {
+        match self.chars.peek() {
+            Some((_, '[')) => self.pair(),
+            Some(&(i, c)) if c.is_ascii_digit() => {
+                self.chars.next();
+                if self.chars.peek().is_some_and(|(_, c)| c.is_ascii_digit()) {
+                    return Err(ParseError {
+                        offset: i,
+                        kind: ParseErrorKind::LargeNumber,
+                    });
+                }
+                Ok(Pair::Num(c.to_digit(10).unwrap() as usize))
+            }
+            _ => Err(self.error(ParseErrorKind::Expected("`[` or a digit"))),
+        }
+    }
+
+    fn pair(&mut self) -> Result<Pair, ParseError> {
+        self.expect('[', "`[`")?;
+        let left = self.element()?;
+        self.expect(',', "`,`")?;
+        let right = self.element()?;
+        self.expect(']', "`]`")?;
+        Ok(left.add(right))
+    }
+}
+
 #[derive(Clone, Eq, PartialEq)]
 enum Pair {
     Num(usize),
@@ -41,12 +122,259 @@ impl std::fmt::Debug for Pair {
     }
 }
 
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Rounding {
+    // 11 splits into [5,6]
+    LeftDown,
+    // 11 splits into [6,5]
+    LeftUp,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+struct SnailfishRules {
+    // pairs nested inside this many pairs explode
+    explode_depth: usize,
+    // regular numbers at least this large split
+    split_threshold: usize,
+    rounding: Rounding,
+    // of the left and right element
+    magnitude_weights: (usize, usize),
+}
+
+impl Default for SnailfishRules {
+    fn default() -> Self {
+        Self {
+            explode_depth: 4,
+            split_threshold: 10,
+            rounding: Rounding::LeftDown,
+            magnitude_weights: (3, 2),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum RulesError {
+    // the outermost pair would explode and take the whole number with it
+    ExplodeDepth(usize),
+    // splitting 1 gives a 1 back, so reducing never ends
+    SplitThreshold(usize),
+}
+
+impl std::fmt::Display for RulesError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::ExplodeDepth(depth) => write!(f, "explode depth {depth} is below 1"),
+            Self::SplitThreshold(threshold) => {
+                write!(f, "split threshold {threshold} is below 2")
+            }
+        }
+    }
+}
+
+impl SnailfishRules {
+    fn new(
+        explode_depth: usize,
+        split_threshold: usize,
+        rounding: Rounding,
+        magnitude_weights: (usize, usize),
+    ) -> Result<Self, RulesError> {
+        if explode_depth < 1 {
+            return Err(RulesError::ExplodeDepth(explode_depth));
+        }
+        if split_threshold < 2 {
+            return Err(RulesError::SplitThreshold(split_threshold));
+        }
+        Ok(Self {
+            explode_depth,
+            split_threshold,
+            rounding,
+            magnitude_weights,
+        })
+    }
+
+    fn halves(&self, n: usize) -> (usize, usize) {
+        match self.rounding {
+            Rounding::LeftDown => (n / 2, n / 2 + n % 2),
+            Rounding::LeftUp => (n / 2 + n % 2, n / 2),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Side {
+    Left,
+    Right,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum ActionKind {
+    Explode,
+    Split,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+struct Action {
+    kind: ActionKind,
+    // from the root to the exploded pair or the split number
+    path: Vec<Side>,
+    // an explode adds these to the nearest numbers on each side, a split makes them a pair
+    left: usize,
+    right: usize,
+    after: Pair,
+}
+
+impl std::fmt::Display for Action {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let path: String = self
+            .path
+            .iter()
+            .map(|side| match side {
+                Side::Left => 'L',
+                Side::Right => 'R',
+            })
+            .collect();
+        match self.kind {
+            ActionKind::Explode => write!(
+                f,
+                "after explode:  {} (at {path}, {} moved left, {} moved right)",
+                self.after, self.left, self.right
+            ),
+            ActionKind::Split => write!(
+                f,
+                "after split:    {} (at {path}, {} into {} and {})",
+                self.after,
+                self.left + self.right,
+                self.left,
+                self.right
+            ),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Goal {
+    Max,
+    Min,
+}
+
+impl Goal {
+    fn parse(s: &str) -> Option<Self> {
+        match s {
+            "max" => Some(Self::Max),
+            "min" => Some(Self::Min),
+            _ => None,
+        }
+    }
+
+    // orders magnitudes best first
+    fn compare(self, a: usize, b: usize) -> std::cmp::Ordering {
+        match self {
+            Self::Max => b.cmp(&a),
+            Self::Min => a.cmp(&b),
+        }
+    }
+}
+
+// orderings of up to this many numbers are searched exhaustively
+const EXACT_ORDER_LIMIT: usize = 8;
+
+impl std::fmt::Display for Pair {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        std::fmt::Debug::fmt(self, f)
+    }
+}
+
+impl std::str::FromStr for Pair {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::parse_with(s, Syntax::Strict)
+    }
+}
+
+// JSON has snailfish numbers as nested two element arrays, just like the puzzle input
+#[cfg(feature = "serde")]
+impl serde::Serialize for Pair {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            Self::Num(n) => serializer.serialize_u64(*n as u64),
+            Self::Cons(left, right) => serde::Serialize::serialize(&(left, right), serializer),
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Pair {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        use serde::de::{Error, IgnoredAny, SeqAccess, Visitor};
+
+        struct PairVisitor;
+
+        impl<'de> Visitor<'de> for PairVisitor {
+            type Value = Pair;
+
+            fn expecting(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                f.write_str("a regular number or an array of two snailfish numbers")
+            }
+
+            fn visit_u64<E: Error>(self, n: u64) -> Result<Pair, E> {
+                usize::try_from(n)
+                    .map(Pair::Num)
+                    .map_err(|_| E::invalid_value(serde::de::Unexpected::Unsigned(n), &self))
+            }
+
+            fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Pair, A::Error> {
+                let left = seq
+                    .next_element()?
+                    .ok_or_else(|| A::Error::invalid_length(0, &self))?;
+                let right = seq
+                    .next_element()?
+                    .ok_or_else(|| A::Error::invalid_length(1, &self))?;
+                if seq.next_element::<IgnoredAny>()?.is_some() {
+                    return Err(A::Error::invalid_length(3, &self));
+                }
+                Ok(Pair::Cons(Box::new(left), Box::new(right)))
+            }
+        }
+
+        deserializer.deserialize_any(PairVisitor)
+    }
+}
+
 impl Pair {
+    #[allow(dead_code)]
     fn parse(s: &str) -> Option<Self> {
+        Self::parse_with(s, Syntax::Lenient).ok()
+    }
+
+    fn parse_with(s: &str, syntax: Syntax) -> Result<Self, ParseError> {
+        match syntax {
+            Syntax::Strict => {
+                let mut parser = StrictParser {
+                    chars: s.chars().enumerate().peekable(),
+                    len: s.chars().count(),
+                };
+                let pair = parser.pair()?;
+                match parser.chars.peek() {
+                    Some(_) => Err(parser.error(ParseErrorKind::TrailingInput)),
+                    None => Ok(pair),
+                }
+            }
+            Syntax::Lenient => Self::parse_lenient(s),
+        }
+    }
+
+    fn parse_lenient(s: &str) -> Result<Self, ParseError> {
         let mut incomplete_pairs: Vec<Option<Pair>> = Vec::new();
-        for token in Token::lexer(s) {
+        let mut lexer = Token::lexer(s);
+        while let Some(token) = lexer.next() {
             match token {
-                Token::Error => return None,
+                Token::Error => {
+                    return Err(ParseError {
+                        offset: s[..lexer.span().start].chars().count(),
+                        kind: ParseErrorKind::Expected("`[`, `]`, `,` or a number"),
+                    })
+                }
                 Token::Open => incomplete_pairs.push(None),
                 Token::Close => {}
                 Token::Comma => {}
@@ -61,19 +389,38 @@ impl Pair {
                                 break;
                             }
                         } else {
-                            return Some(current_pair);
+                            return Ok(current_pair);
                         }
                     }
                 }
             }
         }
-        None
+        Err(ParseError {
+            offset: s.chars().count(),
+            kind: ParseErrorKind::Expected("a complete pair"),
+        })
     }
 
+    #[allow(dead_code)]
     fn parse_multi(s: &str) -> Option<Vec<Self>> {
         s.lines().map(Pair::parse).collect()
     }
 
+    // any whitespace separated JSON values, so newline-delimited files and pretty-printed
+    // documents both work
+    #[cfg(feature = "serde")]
+    fn parse_json_multi(s: &str) -> Result<Vec<Self>, serde_json::Error> {
+        serde_json::Deserializer::from_str(s).into_iter().collect()
+    }
+
+    // errors come with their 1-based line number
+    fn parse_lines(s: &str, syntax: Syntax) -> Result<Vec<Self>, (usize, ParseError)> {
+        s.lines()
+            .enumerate()
+            .map(|(i, line)| Self::parse_with(line, syntax).map_err(|e| (i + 1, e)))
+            .collect()
+    }
+
     fn add(self, rhs: Self) -> Self {
         Self::Cons(Box::new(self), Box::new(rhs))
     }
@@ -85,8 +432,20 @@ impl Pair {
         }
     }
 
+    #[allow(dead_code)]
     fn explode(&mut self) -> Option<(usize, usize)> {
-        self.explode_at_depth(4)
+        self.explode_with(&SnailfishRules::default())
+    }
+
+    // explodes the leftmost of the most deeply nested regular pairs, returning its numbers
+    fn explode_with(&mut self, rules: &SnailfishRules) -> Option<(usize, usize)> {
+        let path = self.explode_path(rules)?;
+        let values = match self.get(&path) {
+            Self::Cons(left, right) => (left.get_num()?, right.get_num()?),
+            Self::Num(_) => return None,
+        };
+        self.explode_at(&path);
+        Some(values)
     }
 
     fn propagate_left_explode(&mut self, val: usize) {
@@ -103,49 +462,64 @@ impl Pair {
         }
     }
 
-    fn explode_at_depth(&mut self, depth: usize) -> Option<(usize, usize)> {
-        match self {
-            Self::Num(_) => None,
-            Self::Cons(left, right) => {
-                if depth == 0 {
-                    Some((left.get_num().unwrap(), right.get_num().unwrap()))
-                } else if let Some((l, r)) = left.explode_at_depth(depth - 1) {
+    // replaces the regular pair at `path` with 0, returns the numbers that still have to be
+    // added to a neighbour on the left and on the right
+    fn explode_at(&mut self, path: &[Side]) -> (Option<usize>, Option<usize>) {
+        let (side, rest) = match path.split_first() {
+            Some(step) => step,
+            None => {
+                let carry = match self {
+                    Self::Cons(left, right) => (left.get_num(), right.get_num()),
+                    Self::Num(_) => (None, None),
+                };
+                *self = Self::Num(0);
+                return carry;
+            }
+        };
+        match (self, side) {
+            (Self::Num(_), _) => (None, None),
+            (Self::Cons(left, right), Side::Left) => {
+                let (l, r) = left.explode_at(rest);
+                if let Some(r) = r {
                     right.propagate_right_explode(r);
-                    if depth == 1 {
-                        *left = Box::new(Self::Num(0));
-                    }
-                    Some((l, 0))
-                } else if let Some((l, r)) = right.explode_at_depth(depth - 1) {
+                }
+                (l, None)
+            }
+            (Self::Cons(left, right), Side::Right) => {
+                let (l, r) = right.explode_at(rest);
+                if let Some(l) = l {
                     left.propagate_left_explode(l);
-                    if depth == 1 {
-                        *right = Box::new(Self::Num(0));
-                    }
-                    Some((0, r))
-                } else {
-                    None
                 }
+                (None, r)
             }
         }
     }
 
+    #[allow(dead_code)]
     fn split(&mut self) -> Option<()> {
+        self.split_with(&SnailfishRules::default())
+    }
+
+    fn split_with(&mut self, rules: &SnailfishRules) -> Option<()> {
         match self {
             Self::Num(n) => {
-                if *n >= 10 {
+                if *n >= rules.split_threshold {
                     Some(())
                 } else {
                     None
                 }
             }
             Self::Cons(left, right) => {
-                if left.split().is_some() {
+                if left.split_with(rules).is_some() {
                     if let Some(n) = left.get_num() {
-                        *left = Box::new(Self::Num(n / 2).add(Self::Num(n / 2 + n % 2)));
+                        let (l, r) = rules.halves(n);
+                        **left = Self::Num(l).add(Self::Num(r));
                     }
                     Some(())
-                } else if right.split().is_some() {
+                } else if right.split_with(rules).is_some() {
                     if let Some(n) = right.get_num() {
-                        *right = Box::new(Self::Num(n / 2).add(Self::Num(n / 2 + n % 2)));
+                        let (l, r) = rules.halves(n);
+                        **right = Self::Num(l).add(Self::Num(r));
                     }
                     Some(())
                 } else {
@@ -155,56 +529,747 @@ impl Pair {
         }
     }
 
+    #[allow(dead_code)]
     fn reduce(&mut self) {
+        self.reduce_with(&SnailfishRules::default())
+    }
+
+    fn reduce_with(&mut self, rules: &SnailfishRules) {
         loop {
-            while self.explode().is_some() {}
-            if self.split().is_none() {
+            while self.explode_with(rules).is_some() {}
+            if self.split_with(rules).is_none() {
                 return;
             }
         }
     }
 
-    fn sum(pairs: &[Self]) -> Self {
-        let (first, rest) = pairs.split_first().unwrap();
-        let mut result = first.clone();
-        for pair in rest {
-            result = result.add(pair.clone());
-            result.reduce();
+    fn get(&self, path: &[Side]) -> &Pair {
+        path.iter().fold(self, |pair, side| match (pair, side) {
+            (Self::Cons(left, _), Side::Left) => left,
+            (Self::Cons(_, right), Side::Right) => right,
+            (Self::Num(_), _) => panic!("path goes through a regular number"),
+        })
+    }
+
+    // leftmost pair or number matching `found` in depth-first order, with the path to it
+    fn find_path(
+        &self,
+        depth: usize,
+        path: &mut Vec<Side>,
+        found: &impl Fn(&Pair, usize) -> bool,
+    ) -> bool {
+        if found(self, depth) {
+            return true;
+        }
+        if let Self::Cons(left, right) = self {
+            for (side, child) in [(Side::Left, left), (Side::Right, right)] {
+                path.push(side);
+                if child.find_path(depth + 1, path, found) {
+                    return true;
+                }
+                path.pop();
+            }
+        }
+        false
+    }
+
+    // leftmost of the most deeply nested pairs of two regular numbers, with its depth
+    fn deepest_regular_pair(&self) -> Option<(usize, Vec<Side>)> {
+        fn walk(
+            pair: &Pair,
+            depth: usize,
+            path: &mut Vec<Side>,
+            deepest: &mut Option<(usize, Vec<Side>)>,
+        ) {
+            if let Pair::Cons(left, right) = pair {
+                if left.get_num().is_some() && right.get_num().is_some() {
+                    if deepest.as_ref().is_none_or(|(max, _)| depth > *max) {
+                        *deepest = Some((depth, path.clone()));
+                    }
+                    return;
+                }
+                for (side, child) in [(Side::Left, left), (Side::Right, right)] {
+                    path.push(side);
+                    walk(child, depth + 1, path, deepest);
+                    path.pop();
+                }
+            }
+        }
+        let mut deepest = None;
+        walk(self, 0, &mut Vec::new(), &mut deepest);
+        deepest
+    }
+
+    // the pair `explode_with` would explode, pairs nested deeper than the rules allow go first
+    // so over-deep input gets normalized bottom up
+    fn explode_path(&self, rules: &SnailfishRules) -> Option<Vec<Side>> {
+        self.deepest_regular_pair()
+            .filter(|(depth, _)| *depth >= rules.explode_depth)
+            .map(|(_, path)| path)
+    }
+
+    // the number `split_with` would split
+    fn split_path(&self, rules: &SnailfishRules) -> Option<Vec<Side>> {
+        let mut path = Vec::new();
+        self.find_path(
+            0,
+            &mut path,
+            &|pair, _| matches!(pair, Self::Num(n) if *n >= rules.split_threshold),
+        )
+        .then_some(path)
+    }
+
+    #[allow(dead_code)]
+    fn reduce_traced(&mut self) -> Vec<Action> {
+        self.reduce_traced_with(&SnailfishRules::default())
+    }
+
+    // like `reduce_with` but returns every step
+    fn reduce_traced_with(&mut self, rules: &SnailfishRules) -> Vec<Action> {
+        let mut actions = Vec::new();
+        loop {
+            let (kind, path, left, right) = if let Some(path) = self.explode_path(rules) {
+                let (left, right) = match self.get(&path) {
+                    Self::Cons(left, right) => (left.get_num().unwrap(), right.get_num().unwrap()),
+                    Self::Num(_) => unreachable!(),
+                };
+                self.explode_at(&path);
+                (ActionKind::Explode, path, left, right)
+            } else if let Some(path) = self.split_path(rules) {
+                let n = self.get(&path).get_num().unwrap();
+                self.split_with(rules);
+                let (left, right) = rules.halves(n);
+                (ActionKind::Split, path, left, right)
+            } else {
+                return actions;
+            };
+            actions.push(Action {
+                kind,
+                path,
+                left,
+                right,
+                after: self.clone(),
+            });
         }
-        result
     }
 
+    // `None` for an empty slice
+    #[allow(dead_code)]
+    fn sum(pairs: &[Self]) -> Option<Self> {
+        pairs
+            .iter()
+            .cloned()
+            .collect::<Option<SnailfishNumber>>()
+            .map(Pair::from)
+    }
+
+    #[allow(dead_code)]
     fn magnitude(&self) -> usize {
+        self.magnitude_with(&SnailfishRules::default())
+    }
+
+    fn magnitude_with(&self, rules: &SnailfishRules) -> usize {
+        let (left_weight, right_weight) = rules.magnitude_weights;
         match self {
             Self::Num(n) => *n,
-            Self::Cons(left, right) => 3 * left.magnitude() + 2 * right.magnitude(),
+            Self::Cons(left, right) => {
+                left_weight * left.magnitude_with(rules)
+                    + right_weight * right.magnitude_with(rules)
+            }
         }
     }
 
+    #[allow(dead_code)]
     fn find_largest_magnitude_of_sum_of_two(pairs: &[Self]) -> usize {
-        let mut max_magnitude = 0;
-        for i in 0..pairs.len() {
-            for j in 0..pairs.len() {
-                if i != j {
-                    let mut sum = pairs[i].clone().add(pairs[j].clone());
-                    sum.reduce();
+        let nums: Vec<SnailfishNumber> = pairs.iter().cloned().map(SnailfishNumber::new).collect();
+        SnailfishNumber::find_largest_magnitude_of_sum_of_two(&nums)
+    }
+}
+
+// always reduced under its rules, there's no way to get at a mutable `Pair` inside
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(from = "Pair", into = "Pair")
+)]
+struct SnailfishNumber {
+    pair: Pair,
+    rules: SnailfishRules,
+}
+
+impl SnailfishNumber {
+    fn new(pair: Pair) -> Self {
+        Self::with_rules(pair, SnailfishRules::default())
+    }
+
+    fn with_rules(mut pair: Pair, rules: SnailfishRules) -> Self {
+        pair.reduce_with(&rules);
+        Self { pair, rules }
+    }
+
+    fn magnitude(&self) -> usize {
+        self.pair.magnitude_with(&self.rules)
+    }
+
+    fn find_largest_magnitude_of_sum_of_two(nums: &[Self]) -> usize {
+        Self::magnitude_matrix(nums)
+            .into_iter()
+            .flatten()
+            .flatten()
+            .max()
+            .unwrap_or(0)
+    }
+
+    // magnitude of nums[i] + nums[j], None on the diagonal
+    fn magnitude_matrix(nums: &[Self]) -> Vec<Vec<Option<usize>>> {
+        (0..nums.len())
+            .map(|i| {
+                (0..nums.len())
+                    .map(|j| (i != j).then(|| (nums[i].clone() + nums[j].clone()).magnitude()))
+                    .collect()
+            })
+            .collect()
+    }
+
+    // the k largest sums of two different numbers as (i, j, magnitude), ties in index order
+    fn top_sums(nums: &[Self], k: usize) -> Vec<(usize, usize, usize)> {
+        let mut sums: Vec<_> = Self::magnitude_matrix(nums)
+            .into_iter()
+            .enumerate()
+            .flat_map(|(i, row)| {
+                row.into_iter()
+                    .enumerate()
+                    .filter_map(move |(j, magnitude)| Some((i, j, magnitude?)))
+            })
+            .collect();
+        sums.sort_by_key(|&(i, j, magnitude)| (std::cmp::Reverse(magnitude), i, j));
+        sums.truncate(k);
+        sums
+    }
+
+    // the order of `nums` whose sum has the best magnitude, as indices into `nums`
+    fn best_order(nums: &[Self], goal: Goal, beam_width: usize) -> Option<(Vec<usize>, usize)> {
+        if nums.len() <= EXACT_ORDER_LIMIT {
+            Self::exact_order(nums, goal)
+        } else {
+            Self::beam_order(nums, goal, beam_width)
+        }
+    }
+
+    // tries every permutation, sharing the sum of common prefixes
+    fn exact_order(nums: &[Self], goal: Goal) -> Option<(Vec<usize>, usize)> {
+        fn search(
+            nums: &[SnailfishNumber],
+            goal: Goal,
+            order: &mut Vec<usize>,
+            sum: Option<&SnailfishNumber>,
+            best: &mut Option<(Vec<usize>, usize)>,
+        ) {
+            if order.len() == nums.len() {
+                if let Some(sum) = sum {
                     let magnitude = sum.magnitude();
-                    if max_magnitude < magnitude {
-                        max_magnitude = magnitude;
+                    if best
+                        .as_ref()
+                        .is_none_or(|(_, best)| goal.compare(magnitude, *best).is_lt())
+                    {
+                        *best = Some((order.clone(), magnitude));
                     }
                 }
+                return;
+            }
+            for i in 0..nums.len() {
+                if order.contains(&i) {
+                    continue;
+                }
+                let next = match sum {
+                    Some(sum) => sum.clone() + nums[i].clone(),
+                    None => nums[i].clone(),
+                };
+                order.push(i);
+                search(nums, goal, order, Some(&next), best);
+                order.pop();
+            }
+        }
+        let mut best = None;
+        search(nums, goal, &mut Vec::new(), None, &mut best);
+        best
+    }
+
+    // extends the `beam_width` best partial sums by one number at a time, never worse than the
+    // order given
+    fn beam_order(nums: &[Self], goal: Goal, beam_width: usize) -> Option<(Vec<usize>, usize)> {
+        // a partial sum's own magnitude says little about the sums it leads to, big sums mostly
+        // explode away on the next addition, so partial sums are scored by adding the unused
+        // number that does best as a right operand across the whole magnitude matrix
+        let matrix = Self::magnitude_matrix(nums);
+        let column = |j: usize| -> usize { matrix.iter().filter_map(|row| row[j]).sum() };
+        let mut right: Vec<usize> = (0..nums.len()).collect();
+        right.sort_by(|&a, &b| goal.compare(column(a), column(b)));
+        let mut beam = vec![(Vec::new(), None::<Self>)];
+        for _ in 0..nums.len() {
+            let mut candidates = Vec::new();
+            for (order, sum) in &beam {
+                for (i, num) in nums.iter().enumerate() {
+                    if order.contains(&i) {
+                        continue;
+                    }
+                    let next = match sum {
+                        Some(sum) => sum.clone() + num.clone(),
+                        None => num.clone(),
+                    };
+                    let mut order = order.clone();
+                    order.push(i);
+                    let score = match right.iter().find(|j| !order.contains(j)) {
+                        Some(&j) => (next.clone() + nums[j].clone()).magnitude(),
+                        None => next.magnitude(),
+                    };
+                    candidates.push((score, order, next));
+                }
+            }
+            candidates.sort_by(|a, b| goal.compare(a.0, b.0));
+            candidates.truncate(beam_width.max(1));
+            beam = candidates
+                .into_iter()
+                .map(|(_, order, sum)| (order, Some(sum)))
+                .collect();
+        }
+        let (order, sum) = beam.into_iter().next()?;
+        let magnitude = sum?.magnitude();
+        let given = nums.iter().sum::<Option<Self>>()?.magnitude();
+        if goal.compare(given, magnitude).is_lt() {
+            Some(((0..nums.len()).collect(), given))
+        } else {
+            Some((order, magnitude))
+        }
+    }
+}
+
+impl From<SnailfishNumber> for Pair {
+    fn from(num: SnailfishNumber) -> Self {
+        num.pair
+    }
+}
+
+impl From<Pair> for SnailfishNumber {
+    fn from(pair: Pair) -> Self {
+        Self::new(pair)
+    }
+}
+
+impl std::str::FromStr for SnailfishNumber {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.parse().map(Self::new)
+    }
+}
+
+impl std::fmt::Display for SnailfishNumber {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.pair.fmt(f)
+    }
+}
+
+// both numbers have to follow the same rules
+impl std::ops::Add for SnailfishNumber {
+    type Output = Self;
+
+    fn add(self, rhs: Self) -> Self {
+        assert_eq!(
+            self.rules, rhs.rules,
+            "adding numbers under different rules"
+        );
+        Self::with_rules(self.pair.add(rhs.pair), self.rules)
+    }
+}
+
+impl std::ops::AddAssign for SnailfishNumber {
+    fn add_assign(&mut self, rhs: Self) {
+        let lhs = std::mem::replace(&mut self.pair, Pair::Num(0));
+        *self = Self {
+            pair: lhs,
+            rules: self.rules,
+        } + rhs
+    }
+}
+
+// `None` for an empty sum
+impl std::iter::Sum<SnailfishNumber> for Option<SnailfishNumber> {
+    fn sum<I: Iterator<Item = SnailfishNumber>>(mut iter: I) -> Self {
+        let mut result = iter.next()?;
+        for num in iter {
+            result += num;
+        }
+        Some(result)
+    }
+}
+
+impl<'a> std::iter::Sum<&'a SnailfishNumber> for Option<SnailfishNumber> {
+    fn sum<I: Iterator<Item = &'a SnailfishNumber>>(iter: I) -> Self {
+        iter.cloned().sum()
+    }
+}
+
+// reduces each pair before adding it
+impl FromIterator<Pair> for Option<SnailfishNumber> {
+    fn from_iter<I: IntoIterator<Item = Pair>>(iter: I) -> Self {
+        iter.into_iter().map(SnailfishNumber::new).sum()
+    }
+}
+
+// leaves from left to right with how many pairs they're nested in, so explode and split are
+// scans over a vector instead of recursions through boxes
+#[derive(Clone, Eq, PartialEq)]
+struct Flat(Vec<(usize, usize)>);
+
+impl std::fmt::Debug for Flat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.to_pair().fmt(f)
+    }
+}
+
+impl Flat {
+    fn from_pair(pair: &Pair) -> Self {
+        fn walk(pair: &Pair, depth: usize, leaves: &mut Vec<(usize, usize)>) {
+            match pair {
+                Pair::Num(n) => leaves.push((*n, depth)),
+                Pair::Cons(left, right) => {
+                    walk(left, depth + 1, leaves);
+                    walk(right, depth + 1, leaves);
+                }
+            }
+        }
+        let mut leaves = Vec::new();
+        walk(pair, 0, &mut leaves);
+        Self(leaves)
+    }
+
+    fn to_pair(&self) -> Pair {
+        fn build(leaves: &[(usize, usize)], next: &mut usize, depth: usize) -> Pair {
+            let (n, leaf_depth) = leaves[*next];
+            if leaf_depth == depth {
+                *next += 1;
+                Pair::Num(n)
+            } else {
+                let left = build(leaves, next, depth + 1);
+                left.add(build(leaves, next, depth + 1))
+            }
+        }
+        build(&self.0, &mut 0, 0)
+    }
+
+    fn add(&self, rhs: &Self) -> Self {
+        Self(
+            self.0
+                .iter()
+                .chain(&rhs.0)
+                .map(|&(n, depth)| (n, depth + 1))
+                .collect(),
+        )
+    }
+
+    // the leftmost leaf at the maximum depth is always the left half of a regular pair
+    fn explode_with(&mut self, rules: &SnailfishRules) -> Option<()> {
+        let max_depth = self.0.iter().map(|&(_, depth)| depth).max()?;
+        if max_depth <= rules.explode_depth {
+            return None;
+        }
+        let i = self.0.iter().position(|&(_, depth)| depth == max_depth)?;
+        let (l, depth) = self.0[i];
+        let (r, _) = self.0.remove(i + 1);
+        if i > 0 {
+            self.0[i - 1].0 += l;
+        }
+        if let Some((n, _)) = self.0.get_mut(i + 1) {
+            *n += r;
+        }
+        self.0[i] = (0, depth - 1);
+        Some(())
+    }
+
+    fn split_with(&mut self, rules: &SnailfishRules) -> Option<()> {
+        let i = self
+            .0
+            .iter()
+            .position(|&(n, _)| n >= rules.split_threshold)?;
+        let (n, depth) = self.0[i];
+        let (left, right) = rules.halves(n);
+        self.0[i] = (left, depth + 1);
+        self.0.insert(i + 1, (right, depth + 1));
+        Some(())
+    }
+
+    fn reduce_with(&mut self, rules: &SnailfishRules) {
+        loop {
+            while self.explode_with(rules).is_some() {}
+            if self.split_with(rules).is_none() {
+                return;
+            }
+        }
+    }
+
+    // `None` for an empty slice
+    fn sum_with(flats: &[Self], rules: &SnailfishRules) -> Option<Self> {
+        let (first, rest) = flats.split_first()?;
+        let mut result = first.clone();
+        result.reduce_with(rules);
+        for flat in rest {
+            result = result.add(flat);
+            result.reduce_with(rules);
+        }
+        Some(result)
+    }
+
+    // neighbours at the same depth are always the two halves of a pair
+    fn magnitude_with(&self, rules: &SnailfishRules) -> usize {
+        let (left_weight, right_weight) = rules.magnitude_weights;
+        let mut stack: Vec<(usize, usize)> = Vec::new();
+        for &leaf in &self.0 {
+            let mut current = leaf;
+            while let Some(&(left, depth)) = stack.last() {
+                if depth != current.1 {
+                    break;
+                }
+                stack.pop();
+                current = (left_weight * left + right_weight * current.0, depth - 1);
+            }
+            stack.push(current);
+        }
+        stack[0].0
+    }
+
+    fn find_largest_magnitude_of_sum_of_two_with(flats: &[Self], rules: &SnailfishRules) -> usize {
+        let mut max_magnitude = 0;
+        for (i, a) in flats.iter().enumerate() {
+            for (j, b) in flats.iter().enumerate() {
+                if i != j {
+                    let mut sum = a.add(b);
+                    sum.reduce_with(rules);
+                    max_magnitude = max_magnitude.max(sum.magnitude_with(rules));
+                }
             }
         }
         max_magnitude
     }
 }
 
+// the puzzle's walkthrough of `Pair::sum`
+fn print_walkthrough(nums: &[Pair], rules: &SnailfishRules) {
+    let mut nums = nums.iter();
+    let mut result = match nums.next() {
+        Some(first) => first.clone(),
+        None => return,
+    };
+    println!("start:          {result}");
+    for action in result.reduce_traced_with(rules) {
+        println!("{action}");
+    }
+    for num in nums {
+        result = result.add(num.clone());
+        println!();
+        println!("after addition: {result}");
+        for action in result.reduce_traced_with(rules) {
+            println!("{action}");
+        }
+    }
+}
+
+// rows and columns are line numbers, the diagonal is left empty
+fn matrix_csv(matrix: &[Vec<Option<usize>>]) -> String {
+    let mut csv = String::new();
+    for j in 0..matrix.len() {
+        write!(csv, ",{}", j + 1).unwrap();
+    }
+    csv.push('\n');
+    for (i, row) in matrix.iter().enumerate() {
+        write!(csv, "{}", i + 1).unwrap();
+        for magnitude in row {
+            csv.push(',');
+            if let Some(magnitude) = magnitude {
+                write!(csv, "{magnitude}").unwrap();
+            }
+        }
+        csv.push('\n');
+    }
+    csv
+}
+
+#[cfg(feature = "serde")]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum JsonMode {
+    Reduce,
+    Sum,
+}
+
+#[cfg(feature = "serde")]
+impl JsonMode {
+    fn parse(s: &str) -> Option<Self> {
+        match s {
+            "reduce" => Some(Self::Reduce),
+            "sum" => Some(Self::Sum),
+            _ => None,
+        }
+    }
+}
+
+// reduces every number of a JSON document or sums them, printing JSON
+#[cfg(feature = "serde")]
+fn run_json(input: &str, mode: JsonMode, rules: &SnailfishRules) {
+    let nums = match Pair::parse_json_multi(input) {
+        Ok(nums) => nums,
+        Err(e) => {
+            eprintln!("{e}");
+            std::process::exit(1);
+        }
+    };
+    match mode {
+        JsonMode::Reduce => {
+            for num in nums {
+                let num = SnailfishNumber::with_rules(num, *rules);
+                println!("{}", serde_json::to_string(&num).unwrap());
+            }
+        }
+        JsonMode::Sum => {
+            let sum = nums
+                .into_iter()
+                .map(|num| SnailfishNumber::with_rules(num, *rules))
+                .sum::<Option<SnailfishNumber>>();
+            match sum {
+                Some(sum) => println!("{}", serde_json::to_string(&sum).unwrap()),
+                None => {
+                    eprintln!("no numbers to sum");
+                    std::process::exit(1);
+                }
+            }
+        }
+    }
+}
+
+fn rules_from_args(args: &[String]) -> Result<SnailfishRules, RulesError> {
+    let option = |prefix: &str| args.iter().find_map(|arg| arg.strip_prefix(prefix));
+    let defaults = SnailfishRules::default();
+    SnailfishRules::new(
+        option("--explode-depth=").map_or(defaults.explode_depth, |n| n.parse().unwrap()),
+        option("--split-threshold=").map_or(defaults.split_threshold, |n| n.parse().unwrap()),
+        if args.iter().any(|arg| arg == "--round-left-up") {
+            Rounding::LeftUp
+        } else {
+            defaults.rounding
+        },
+        option("--weights=").map_or(defaults.magnitude_weights, |weights| {
+            let (left, right) = weights.split_once(',').expect("expected LEFT,RIGHT");
+            (left.parse().unwrap(), right.parse().unwrap())
+        }),
+    )
+}
+
+// usage: day18 [--flat|--walkthrough|--matrix|--top=K|--order=max|min [--beam=W]] [--lenient]
+//              [RULES] [FILE]
+//        day18 --json=reduce|sum [RULES] [FILE]
+// RULES is any of --explode-depth=N --split-threshold=N --round-left-up --weights=LEFT,RIGHT
+// with an explode depth of at least 1 and a split threshold of at least 2. --json needs the
+// serde feature and reads any JSON values separated by whitespace
 fn main() {
-    let nums = Pair::parse_multi(include_str!("../../inputs/day18.txt")).unwrap();
-    println!("Part 1: {}", Pair::sum(&nums).magnitude());
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let flag = |name: &str| args.iter().any(|arg| arg == name);
+    let option = |prefix: &str| args.iter().find_map(|arg| arg.strip_prefix(prefix));
+    let rules = match rules_from_args(&args) {
+        Ok(rules) => rules,
+        Err(e) => {
+            eprintln!("{e}");
+            std::process::exit(1);
+        }
+    };
+    #[cfg(feature = "serde")]
+    let json_mode = option("--json=").map(|mode| {
+        JsonMode::parse(mode).unwrap_or_else(|| {
+            eprintln!("expected --json=reduce or --json=sum, got --json={mode}");
+            std::process::exit(1);
+        })
+    });
+    let input = match args.iter().find(|arg| !arg.starts_with("--")) {
+        Some(path) => std::fs::read_to_string(path).unwrap(),
+        None => include_str!("../../inputs/day18.txt").to_string(),
+    };
+    #[cfg(feature = "serde")]
+    if let Some(mode) = json_mode {
+        run_json(&input, mode, &rules);
+        return;
+    }
+    let syntax = if flag("--lenient") {
+        Syntax::Lenient
+    } else {
+        Syntax::Strict
+    };
+    let pairs = match Pair::parse_lines(&input, syntax) {
+        Ok(pairs) => pairs,
+        Err((line, e)) => {
+            eprintln!("line {line}: {e}");
+            std::process::exit(1);
+        }
+    };
+    if flag("--walkthrough") {
+        print_walkthrough(&pairs, &rules);
+        return;
+    }
+    if flag("--flat") {
+        // reduced first, like a SnailfishNumber, as the input is only reduced under the
+        // puzzle's rules
+        let nums: Vec<Flat> = pairs
+            .iter()
+            .map(|pair| {
+                let mut flat = Flat::from_pair(pair);
+                flat.reduce_with(&rules);
+                flat
+            })
+            .collect();
+        println!(
+            "Part 1: {}",
+            Flat::sum_with(&nums, &rules)
+                .expect("no numbers")
+                .magnitude_with(&rules)
+        );
+        println!(
+            "Part 2: {}",
+            Flat::find_largest_magnitude_of_sum_of_two_with(&nums, &rules)
+        );
+        return;
+    }
+    let nums: Vec<SnailfishNumber> = pairs
+        .into_iter()
+        .map(|pair| SnailfishNumber::with_rules(pair, rules))
+        .collect();
+    if flag("--matrix") {
+        print!("{}", matrix_csv(&SnailfishNumber::magnitude_matrix(&nums)));
+        return;
+    }
+    if let Some(k) = option("--top=") {
+        for (i, j, magnitude) in SnailfishNumber::top_sums(&nums, k.parse().unwrap()) {
+            println!("line {} + line {}: {magnitude}", i + 1, j + 1);
+        }
+        return;
+    }
+    if let Some(goal) = option("--order=") {
+        let goal = Goal::parse(goal).expect("expected --order=max or --order=min");
+        let beam_width = option("--beam=").map_or(16, |w| w.parse().unwrap());
+        let (order, magnitude) =
+            SnailfishNumber::best_order(&nums, goal, beam_width).expect("no numbers");
+        let lines: Vec<String> = order.iter().map(|i| (i + 1).to_string()).collect();
+        println!("order: {}", lines.join(","));
+        println!("magnitude: {magnitude}");
+        return;
+    }
+    println!(
+        "Part 1: {}",
+        nums.iter()
+            .sum::<Option<SnailfishNumber>>()
+            .expect("no numbers")
+            .magnitude()
+    );
     println!(
         "Part 2: {}",
-        Pair::find_largest_magnitude_of_sum_of_two(&nums)
+        SnailfishNumber::find_largest_magnitude_of_sum_of_two(&nums)
     );
 }
 
@@ -213,6 +1278,78 @@ mod tests {
     use super::Pair::*;
     use super::*;
 
+    #[test]
+    fn test_strict_parse() {
+        let error = |offset, kind| Err(ParseError { offset, kind });
+        let expected = |offset, expected| error(offset, ParseErrorKind::Expected(expected));
+        assert_eq!(
+            "[1,2]".parse(),
+            Ok(Cons(Box::new(Num(1)), Box::new(Num(2))))
+        );
+        assert_eq!("".parse::<Pair>(), expected(0, "`[`"));
+        assert_eq!("7".parse::<Pair>(), expected(0, "`[`"));
+        assert_eq!("[1,".parse::<Pair>(), expected(3, "`[` or a digit"));
+        assert_eq!("[1,2,3".parse::<Pair>(), expected(4, "`]`"));
+        assert_eq!("[1 ,2]".parse::<Pair>(), expected(2, "`,`"));
+        assert_eq!(
+            "[12]".parse::<Pair>(),
+            error(1, ParseErrorKind::LargeNumber)
+        );
+        assert_eq!(
+            "[1,[2,10]]".parse::<Pair>(),
+            error(6, ParseErrorKind::LargeNumber)
+        );
+        assert_eq!(
+            "[1,2]]".parse::<Pair>(),
+            error(5, ParseErrorKind::TrailingInput)
+        );
+        // offsets count characters, not bytes
+        assert_eq!("[é,2]".parse::<Pair>(), expected(1, "`[` or a digit"));
+        assert_eq!(
+            "[1,2,3".parse::<Pair>().unwrap_err().to_string(),
+            "expected `]` at offset 4"
+        );
+
+        assert_eq!(
+            Pair::parse_with("[1,2,3", Syntax::Lenient),
+            Ok(Cons(Box::new(Num(1)), Box::new(Num(2))))
+        );
+        assert_eq!(
+            Pair::parse_with("[12,3]", Syntax::Lenient),
+            Ok(Cons(Box::new(Num(12)), Box::new(Num(3))))
+        );
+        assert_eq!(
+            Pair::parse_with("[1,x]", Syntax::Lenient),
+            expected(3, "`[`, `]`, `,` or a number")
+        );
+        assert_eq!(
+            Pair::parse_with("[1,", Syntax::Lenient),
+            expected(3, "a complete pair")
+        );
+        assert_eq!(
+            Pair::parse_lines("[1,2]\n[3,4]\n[5,6", Syntax::Strict),
+            Err((
+                3,
+                ParseError {
+                    offset: 4,
+                    kind: ParseErrorKind::Expected("`]`")
+                }
+            ))
+        );
+    }
+
+    #[test]
+    fn test_display() {
+        let input = include_str!("../../inputs/day18.txt");
+        for (line, pair) in input
+            .lines()
+            .zip(Pair::parse_lines(input, Syntax::Strict).unwrap())
+        {
+            assert_eq!(pair.to_string(), line);
+            assert_eq!(pair.to_string(), format!("{pair:?}"));
+        }
+    }
+
     #[test]
     fn test_pair_parse() {
         assert_eq!(Pair::parse(""), None);
@@ -261,6 +1398,16 @@ mod tests {
         assert_eq!(num, Pair::parse(output).unwrap());
     }
 
+    fn flat_should<F: FnOnce(&mut Flat) -> Option<()>>(input: &str, transform: F, output: &str) {
+        let mut num = Flat::from_pair(&Pair::parse(input).unwrap());
+        assert!(transform(&mut num).is_some());
+        assert_eq!(num.to_pair(), Pair::parse(output).unwrap());
+    }
+
+    fn explode_flat(flat: &mut Flat) -> Option<()> {
+        flat.explode_with(&SnailfishRules::default())
+    }
+
     fn should_not<R, F: FnOnce(&mut Pair) -> Option<R>>(input: &str, transform: F) {
         let mut num = Pair::parse(input).unwrap();
         let original = num.clone();
@@ -316,8 +1463,14 @@ mod tests {
 
     fn list_should_sum_to(input: &str, output: &str) {
         let nums = Pair::parse_multi(input).unwrap();
-        let result = Pair::sum(&nums);
+        let result = Pair::sum(&nums).unwrap();
         assert_eq!(result, Pair::parse(output).unwrap());
+
+        let flats: Vec<Flat> = nums.iter().map(Flat::from_pair).collect();
+        let rules = SnailfishRules::default();
+        let flat = Flat::sum_with(&flats, &rules).unwrap();
+        assert_eq!(flat.to_pair(), result);
+        assert_eq!(flat.magnitude_with(&rules), result.magnitude());
     }
 
     #[test]
@@ -505,6 +1658,61 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_reduce_traced() {
+        let mut num = Pair::parse("[[[[[4,3],4],4],[7,[[8,4],9]]],[1,1]]").unwrap();
+        let actions = num.reduce_traced();
+        let summary: Vec<(ActionKind, &[Side], usize, usize)> = actions
+            .iter()
+            .map(|action| (action.kind, &action.path[..], action.left, action.right))
+            .collect();
+        use {ActionKind::*, Side::*};
+        assert_eq!(
+            summary,
+            [
+                (Explode, &[Left, Left, Left, Left][..], 4, 3),
+                (Explode, &[Left, Right, Right, Left][..], 8, 4),
+                (Split, &[Left, Right, Left][..], 7, 8),
+                (Split, &[Left, Right, Right, Right][..], 6, 7),
+                (Explode, &[Left, Right, Right, Right][..], 6, 7),
+            ]
+        );
+        let afters: Vec<String> = actions
+            .iter()
+            .map(|action| action.after.to_string())
+            .collect();
+        assert_eq!(
+            afters,
+            [
+                "[[[[0,7],4],[7,[[8,4],9]]],[1,1]]",
+                "[[[[0,7],4],[15,[0,13]]],[1,1]]",
+                "[[[[0,7],4],[[7,8],[0,13]]],[1,1]]",
+                "[[[[0,7],4],[[7,8],[0,[6,7]]]],[1,1]]",
+                "[[[[0,7],4],[[7,8],[6,0]]],[8,1]]",
+            ]
+        );
+        assert_eq!(num.to_string(), afters[4]);
+        assert_eq!(
+            actions[0].to_string(),
+            "after explode:  [[[[0,7],4],[7,[[8,4],9]]],[1,1]] (at LLLL, 4 moved left, 3 moved right)"
+        );
+        assert_eq!(
+            actions[2].to_string(),
+            "after split:    [[[[0,7],4],[[7,8],[0,13]]],[1,1]] (at LRL, 15 into 7 and 8)"
+        );
+
+        // same result as reducing silently
+        let mut traced = Pair::parse(
+            "[[[[0,[4,5]],[0,0]],[[[4,5],[2,6]],[9,5]]],[7,[[[3,7],[4,3]],[[6,3],[8,8]]]]]",
+        )
+        .unwrap();
+        let mut silent = traced.clone();
+        assert_eq!(traced.reduce_traced().len(), 21);
+        silent.reduce();
+        assert_eq!(traced, silent);
+        assert!(traced.reduce_traced().is_empty());
+    }
+
     #[test]
     fn test_complex_reduce_but_with_reduce() {
         should(
@@ -562,7 +1770,7 @@ mod tests {
     #[test]
     fn test_part1_last_sample() {
         let nums = Pair::parse_multi("[[[0,[5,8]],[[1,7],[9,6]]],[[4,[1,2]],[[1,4],2]]]\n[[[5,[2,8]],4],[5,[[9,9],0]]]\n[6,[[[6,2],[5,6]],[[7,6],[4,7]]]]\n[[[6,[0,7]],[0,9]],[4,[9,[9,0]]]]\n[[[7,[6,4]],[3,[1,3]]],[[[5,5],1],9]]\n[[6,[[7,3],[3,2]]],[[[3,8],[5,7]],4]]\n[[[[5,4],[7,7]],8],[[8,3],8]]\n[[9,3],[[9,9],[6,[4,9]]]]\n[[2,[[7,7],7]],[[5,8],[[9,3],[0,2]]]]\n[[[[5,2],5],[8,[3,7]]],[[5,[7,5]],[4,4]]]").unwrap();
-        let num = Pair::sum(&nums);
+        let num = Pair::sum(&nums).unwrap();
         assert_eq!(
             num,
             Pair::parse("[[[[6,6],[7,6]],[[7,7],[7,0]]],[[[7,7],[7,7]],[[7,8],[9,9]]]]").unwrap()
@@ -574,5 +1782,432 @@ mod tests {
     fn test_part2_sample() {
         let nums = Pair::parse_multi("[[[0,[5,8]],[[1,7],[9,6]]],[[4,[1,2]],[[1,4],2]]]\n[[[5,[2,8]],4],[5,[[9,9],0]]]\n[6,[[[6,2],[5,6]],[[7,6],[4,7]]]]\n[[[6,[0,7]],[0,9]],[4,[9,[9,0]]]]\n[[[7,[6,4]],[3,[1,3]]],[[[5,5],1],9]]\n[[6,[[7,3],[3,2]]],[[[3,8],[5,7]],4]]\n[[[[5,4],[7,7]],8],[[8,3],8]]\n[[9,3],[[9,9],[6,[4,9]]]]\n[[2,[[7,7],7]],[[5,8],[[9,3],[0,2]]]]\n[[[[5,2],5],[8,[3,7]]],[[5,[7,5]],[4,4]]]").unwrap();
         assert_eq!(Pair::find_largest_magnitude_of_sum_of_two(&nums), 3993);
+        let flats: Vec<Flat> = nums.iter().map(Flat::from_pair).collect();
+        assert_eq!(
+            Flat::find_largest_magnitude_of_sum_of_two_with(&flats, &SnailfishRules::default()),
+            3993
+        );
+    }
+
+    #[test]
+    fn test_top_sums() {
+        let nums = Pair::parse_multi("[[[0,[5,8]],[[1,7],[9,6]]],[[4,[1,2]],[[1,4],2]]]\n[[[5,[2,8]],4],[5,[[9,9],0]]]\n[6,[[[6,2],[5,6]],[[7,6],[4,7]]]]\n[[[6,[0,7]],[0,9]],[4,[9,[9,0]]]]\n[[[7,[6,4]],[3,[1,3]]],[[[5,5],1],9]]\n[[6,[[7,3],[3,2]]],[[[3,8],[5,7]],4]]\n[[[[5,4],[7,7]],8],[[8,3],8]]\n[[9,3],[[9,9],[6,[4,9]]]]\n[[2,[[7,7],7]],[[5,8],[[9,3],[0,2]]]]\n[[[[5,2],5],[8,[3,7]]],[[5,[7,5]],[4,4]]]").unwrap();
+        let nums: Vec<SnailfishNumber> = nums.into_iter().map(SnailfishNumber::new).collect();
+        let top = SnailfishNumber::top_sums(&nums, 5);
+        assert_eq!(top[0], (8, 0, 3993));
+        assert_eq!(top.len(), 5);
+        assert!(top.windows(2).all(|w| w[0].2 >= w[1].2));
+        assert_eq!(SnailfishNumber::top_sums(&nums, 1000).len(), 90);
+        assert!(SnailfishNumber::top_sums(&nums[..1], 3).is_empty());
+
+        let matrix = SnailfishNumber::magnitude_matrix(&nums);
+        assert_eq!(matrix[8][0], Some(3993));
+        assert_eq!(matrix[3][3], None);
+        let csv = matrix_csv(&matrix[..2]);
+        let mut lines = csv.lines();
+        assert_eq!(lines.next(), Some(",1,2"));
+        assert!(lines.next().unwrap().starts_with("1,,"));
+    }
+
+    #[test]
+    fn test_best_order() {
+        let nums = Pair::parse_multi("[[[0,[5,8]],[[1,7],[9,6]]],[[4,[1,2]],[[1,4],2]]]\n[[[5,[2,8]],4],[5,[[9,9],0]]]\n[6,[[[6,2],[5,6]],[[7,6],[4,7]]]]\n[[[6,[0,7]],[0,9]],[4,[9,[9,0]]]]\n[[[7,[6,4]],[3,[1,3]]],[[[5,5],1],9]]").unwrap();
+        let in_order = Pair::sum(&nums).unwrap().magnitude();
+        let sum_in = |order: &[usize]| {
+            let reordered: Vec<Pair> = order.iter().map(|&i| nums[i].clone()).collect();
+            Pair::sum(&reordered).unwrap().magnitude()
+        };
+        let nums: Vec<SnailfishNumber> = nums.iter().cloned().map(SnailfishNumber::new).collect();
+        for goal in [Goal::Max, Goal::Min] {
+            let (order, magnitude) = SnailfishNumber::best_order(&nums, goal, 1).unwrap();
+            let mut sorted = order.clone();
+            sorted.sort();
+            assert_eq!(sorted, (0..nums.len()).collect::<Vec<_>>());
+            assert_eq!(sum_in(&order), magnitude);
+            assert!(goal.compare(magnitude, in_order).is_le());
+            // wide enough to keep every prefix, so the beam search is exhaustive too
+            let beam = SnailfishNumber::beam_order(&nums, goal, 120).unwrap();
+            assert_eq!(beam.1, magnitude);
+            let greedy = SnailfishNumber::beam_order(&nums, goal, 1).unwrap();
+            assert_eq!(sum_in(&greedy.0), greedy.1);
+            assert!(goal.compare(magnitude, greedy.1).is_le());
+            assert!(goal.compare(greedy.1, in_order).is_le());
+        }
+        assert_eq!(SnailfishNumber::best_order(&[], Goal::Max, 4), None);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde_json() {
+        let pair = Pair::parse("[[1,2],[[3,4],5]]").unwrap();
+        assert_eq!(serde_json::to_string(&pair).unwrap(), "[[1,2],[[3,4],5]]");
+        assert_eq!(
+            serde_json::to_value(&pair).unwrap(),
+            serde_json::json!([[1, 2], [[3, 4], 5]])
+        );
+        assert_eq!(
+            serde_json::from_value::<Pair>(serde_json::json!([[1, 2], [[3, 4], 5]])).unwrap(),
+            pair
+        );
+        assert_eq!(serde_json::from_str::<Pair>("7").unwrap(), Num(7));
+        for invalid in [
+            "[1]",
+            "[]",
+            "[1,2,3]",
+            "[1,-2]",
+            "[1,2.5]",
+            "[\"1\",2]",
+            "{}",
+        ] {
+            assert!(serde_json::from_str::<Pair>(invalid).is_err(), "{invalid}");
+        }
+
+        // unreduced input comes out reduced as a SnailfishNumber
+        let num: SnailfishNumber =
+            serde_json::from_str("[[[[[4,3],4],4],[7,[[8,4],9]]],[1,1]]").unwrap();
+        assert_eq!(num.to_string(), "[[[[0,7],4],[[7,8],[6,0]]],[8,1]]");
+        assert_eq!(
+            serde_json::to_string(&num).unwrap(),
+            "[[[[0,7],4],[[7,8],[6,0]]],[8,1]]"
+        );
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_parse_json_multi() {
+        let nums = Pair::parse_json_multi("[1, 2]\n[[3,4],\n  5]\n\n[6,7]\n").unwrap();
+        assert_eq!(nums, Pair::parse_multi("[1,2]\n[[3,4],5]\n[6,7]").unwrap());
+        let input = include_str!("../../inputs/day18.txt");
+        assert_eq!(
+            Pair::parse_json_multi(input).unwrap(),
+            Pair::parse_multi(input).unwrap()
+        );
+        let e = Pair::parse_json_multi("[1,2]\n[3,4,5]").unwrap_err();
+        assert_eq!(e.line(), 2);
+        assert!(Pair::parse_json_multi("").unwrap().is_empty());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_json_mode() {
+        assert_eq!(JsonMode::parse("reduce"), Some(JsonMode::Reduce));
+        assert_eq!(JsonMode::parse("sum"), Some(JsonMode::Sum));
+        assert_eq!(JsonMode::parse("sums"), None);
+        assert_eq!(JsonMode::parse(""), None);
+    }
+
+    #[test]
+    fn test_snailfish_number() {
+        let a: SnailfishNumber = "[[[[4,3],4],4],[7,[[8,4],9]]]".parse().unwrap();
+        let b: SnailfishNumber = "[1,1]".parse().unwrap();
+        let expected: SnailfishNumber = "[[[[0,7],4],[[7,8],[6,0]]],[8,1]]".parse().unwrap();
+        assert_eq!(a.clone() + b.clone(), expected);
+        let mut sum = a;
+        sum += b;
+        assert_eq!(sum, expected);
+        assert_eq!(sum.to_string(), "[[[[0,7],4],[[7,8],[6,0]]],[8,1]]");
+
+        // parsing and converting reduce right away
+        let exploded: SnailfishNumber = "[[[[[9,8],1],2],3],4]".parse().unwrap();
+        assert_eq!(
+            Pair::from(exploded),
+            Pair::parse("[[[[0,9],2],3],4]").unwrap()
+        );
+        assert_eq!(
+            Pair::from(SnailfishNumber::from(Cons(
+                Box::new(Num(10)),
+                Box::new(Num(1))
+            ))),
+            Pair::parse("[[5,5],1]").unwrap()
+        );
+
+        let nums: Vec<SnailfishNumber> = ["[1,1]", "[2,2]", "[3,3]", "[4,4]", "[5,5]"]
+            .iter()
+            .map(|s| s.parse().unwrap())
+            .collect();
+        let expected = "[[[[3,0],[5,3]],[4,4]],[5,5]]".parse().unwrap();
+        assert_eq!(nums.iter().sum::<Option<SnailfishNumber>>(), Some(expected));
+        let pairs = nums.into_iter().map(Pair::from);
+        assert_eq!(
+            pairs
+                .collect::<Option<SnailfishNumber>>()
+                .unwrap()
+                .to_string(),
+            "[[[[3,0],[5,3]],[4,4]],[5,5]]"
+        );
+
+        assert_eq!(
+            Vec::<SnailfishNumber>::new().into_iter().sum::<Option<_>>(),
+            None
+        );
+        assert_eq!(Pair::sum(&[]), None);
+        assert_eq!(Flat::sum_with(&[], &SnailfishRules::default()), None);
+    }
+
+    #[test]
+    fn test_rules() {
+        let rules = SnailfishRules::default();
+        let nums = Pair::parse_multi(include_str!("../../inputs/day18.txt")).unwrap();
+        let sum = nums
+            .iter()
+            .map(|pair| SnailfishNumber::with_rules(pair.clone(), rules))
+            .sum::<Option<_>>()
+            .map(Pair::from);
+        assert_eq!(sum, Pair::sum(&nums));
+
+        // explode at depth 3 and split from 5 up, rounding the left half up
+        let rules = SnailfishRules::new(3, 5, Rounding::LeftUp, (1, 1)).unwrap();
+        let mut num = Pair::parse("[[[[1,2],3],4],5]").unwrap();
+        num.reduce_with(&rules);
+        assert_eq!(num, Pair::parse("[[[3,0],[3,3]],[3,2]]").unwrap());
+        let actions = Pair::parse("[[[[1,2],3],4],5]")
+            .unwrap()
+            .reduce_traced_with(&rules);
+        assert_eq!(actions.last().unwrap().after, num);
+        assert_eq!(num.magnitude_with(&rules), 14);
+        assert_eq!(num.magnitude(), 3 * (3 * 9 + 2 * 15) + 2 * 13);
+        let mut flat = Flat::from_pair(&Pair::parse("[[[[1,2],3],4],5]").unwrap());
+        flat.reduce_with(&rules);
+        assert_eq!(flat.to_pair(), num);
+        assert_eq!(flat.magnitude_with(&rules), 14);
+
+        let variant = SnailfishRules::new(3, 7, Rounding::LeftUp, (2, 5)).unwrap();
+        let flats: Vec<Flat> = nums
+            .iter()
+            .map(|pair| {
+                let mut flat = Flat::from_pair(pair);
+                flat.reduce_with(&variant);
+                flat
+            })
+            .collect();
+        let sum = nums
+            .iter()
+            .map(|pair| SnailfishNumber::with_rules(pair.clone(), variant))
+            .sum::<Option<SnailfishNumber>>()
+            .unwrap();
+        let flat = Flat::sum_with(&flats, &variant).unwrap();
+        assert_eq!(flat.to_pair(), Pair::from(sum.clone()));
+        assert_eq!(flat.magnitude_with(&variant), sum.magnitude());
+
+        let sum = SnailfishNumber::with_rules(Pair::parse("[1,2]").unwrap(), rules)
+            + SnailfishNumber::with_rules(Pair::parse("[3,6]").unwrap(), rules);
+        assert_eq!(
+            Pair::from(sum.clone()),
+            Pair::parse("[[1,2],[3,[3,3]]]").unwrap()
+        );
+        assert_eq!(sum.magnitude(), 12);
+
+        // rules that would never finish reducing or keep nothing are rejected
+        let rules =
+            |depth, threshold| SnailfishRules::new(depth, threshold, Rounding::LeftUp, (3, 2));
+        assert_eq!(rules(4, 1), Err(RulesError::SplitThreshold(1)));
+        assert_eq!(rules(4, 0), Err(RulesError::SplitThreshold(0)));
+        assert_eq!(rules(0, 10), Err(RulesError::ExplodeDepth(0)));
+        assert!(rules(1, 2).is_ok());
+        let args = ["--split-threshold=1".to_string()];
+        assert_eq!(rules_from_args(&args), Err(RulesError::SplitThreshold(1)));
+    }
+
+    #[test]
+    fn test_flat() {
+        let pair =
+            Pair::parse("[[[[1,3],[5,3]],[[1,3],[8,7]]],[[[4,9],[6,9]],[[8,2],[7,3]]]]").unwrap();
+        let flat = Flat::from_pair(&pair);
+        assert_eq!(flat.0[..3], [(1, 4), (3, 4), (5, 4)]);
+        assert_eq!(flat.to_pair(), pair);
+        assert_eq!(Flat::from_pair(&Num(7)).to_pair(), Num(7));
+
+        flat_should("[[[[[9,8],1],2],3],4]", explode_flat, "[[[[0,9],2],3],4]");
+        flat_should("[7,[6,[5,[4,[3,2]]]]]", explode_flat, "[7,[6,[5,[7,0]]]]");
+        flat_should(
+            "[[3,[2,[1,[7,3]]]],[6,[5,[4,[3,2]]]]]",
+            explode_flat,
+            "[[3,[2,[8,0]]],[9,[5,[4,[3,2]]]]]",
+        );
+        flat_should(
+            "[[[[0,7],4],[15,[0,13]]],[1,1]]",
+            |flat| flat.split_with(&SnailfishRules::default()),
+            "[[[[0,7],4],[[7,8],[0,13]]],[1,1]]",
+        );
+        let mut flat =
+            Flat::from_pair(&Pair::parse("[[[[[4,3],4],4],[7,[[8,4],9]]],[1,1]]").unwrap());
+        flat.reduce_with(&SnailfishRules::default());
+        assert_eq!(
+            flat.to_pair(),
+            Pair::parse("[[[[0,7],4],[[7,8],[6,0]]],[8,1]]").unwrap()
+        );
+    }
+
+    #[test]
+    fn test_over_deep() {
+        should(
+            "[[[[[[1,2],3],4],5],6],7]",
+            Pair::explode,
+            "[[[[[0,5],4],5],6],7]",
+        );
+        should("[[[[[0,5],4],5],6],7]", Pair::explode, "[[[[0,9],5],6],7]");
+        should(
+            "[[[[[1,[2,3]],4],5],6],7]",
+            Pair::explode,
+            "[[[[[3,0],7],5],6],7]",
+        );
+        flat_should(
+            "[[[[[1,[2,3]],4],5],6],7]",
+            explode_flat,
+            "[[[[[3,0],7],5],6],7]",
+        );
+        should("[[[[[[1,2],3],4],5],6],7]", reduce, "[[[[0,9],5],6],7]");
+    }
+
+    #[derive(Clone, Copy)]
+    enum Tok {
+        Open,
+        Close,
+        Num(usize),
+    }
+
+    // reduces on the token stream, the way the puzzle text describes it
+    fn reference_reduce(input: &str) -> String {
+        let mut toks = Vec::new();
+        for (i, c) in input.char_indices() {
+            match c {
+                '[' => toks.push(Tok::Open),
+                ']' => toks.push(Tok::Close),
+                '0'..='9' if !input[..i].ends_with(|c: char| c.is_ascii_digit()) => {
+                    let digits = input[i..].find(|c: char| !c.is_ascii_digit()).unwrap();
+                    toks.push(Tok::Num(input[i..i + digits].parse().unwrap()));
+                }
+                _ => {}
+            }
+        }
+        loop {
+            let mut depth = 0;
+            let mut deepest: Option<(usize, usize)> = None;
+            for i in 0..toks.len() {
+                match toks[i..] {
+                    [Tok::Open, Tok::Num(_), Tok::Num(_), Tok::Close, ..]
+                        if deepest.is_none_or(|(max, _)| depth > max) =>
+                    {
+                        deepest = Some((depth, i));
+                        depth += 1;
+                    }
+                    [Tok::Open, ..] => depth += 1,
+                    [Tok::Close, ..] => depth -= 1,
+                    _ => {}
+                }
+            }
+            if let Some((_, i)) = deepest.filter(|&(depth, _)| depth >= 4) {
+                let (Tok::Num(l), Tok::Num(r)) = (toks[i + 1], toks[i + 2]) else {
+                    unreachable!()
+                };
+                if let Some(Tok::Num(n)) = toks[..i]
+                    .iter_mut()
+                    .rev()
+                    .find(|t| matches!(t, Tok::Num(_)))
+                {
+                    *n += l;
+                }
+                if let Some(Tok::Num(n)) =
+                    toks[i + 4..].iter_mut().find(|t| matches!(t, Tok::Num(_)))
+                {
+                    *n += r;
+                }
+                toks.splice(i..i + 4, [Tok::Num(0)]);
+                continue;
+            }
+            if let Some(i) = toks
+                .iter()
+                .position(|t| matches!(t, Tok::Num(n) if *n >= 10))
+            {
+                let Tok::Num(n) = toks[i] else { unreachable!() };
+                toks.splice(
+                    i..i + 1,
+                    [Tok::Open, Tok::Num(n / 2), Tok::Num(n - n / 2), Tok::Close],
+                );
+                continue;
+            }
+            break;
+        }
+        let mut out = String::new();
+        for (i, tok) in toks.iter().enumerate() {
+            if i > 0 && !matches!(tok, Tok::Close) && !matches!(toks[i - 1], Tok::Open) {
+                out.push(',');
+            }
+            match tok {
+                Tok::Open => out.push('['),
+                Tok::Close => out.push(']'),
+                Tok::Num(n) => out.push_str(&n.to_string()),
+            }
+        }
+        out
+    }
+
+    // shaped by `choices`, any sequence that isn't too regular will do
+    fn generated_pair(
+        choices: &mut impl Iterator<Item = usize>,
+        depth: usize,
+        max_depth: usize,
+    ) -> String {
+        if depth > 0 && (depth == max_depth || choices.next().unwrap().is_multiple_of(3)) {
+            (choices.next().unwrap() % 16).to_string()
+        } else {
+            let left = generated_pair(choices, depth + 1, max_depth);
+            let right = generated_pair(choices, depth + 1, max_depth);
+            format!("[{left},{right}]")
+        }
+    }
+
+    #[test]
+    fn test_over_deep_generated() {
+        assert_eq!(
+            reference_reduce("[[[[[4,3],4],4],[7,[[8,4],9]]],[1,1]]"),
+            "[[[[0,7],4],[[7,8],[6,0]]],[8,1]]"
+        );
+        let mut choices = (0..).map(|i: usize| i * i % 101 + i / 7);
+        for max_depth in 1..=10 {
+            for _ in 0..50 {
+                let input = generated_pair(&mut choices, 0, max_depth);
+                let expected = reference_reduce(&input);
+                let mut pair = Pair::parse(&input).unwrap();
+                let mut flat = Flat::from_pair(&pair);
+                pair.reduce();
+                flat.reduce_with(&SnailfishRules::default());
+                assert_eq!(pair.to_string(), expected, "{input}");
+                assert_eq!(flat.to_pair().to_string(), expected, "{input}");
+                let mut traced = Pair::parse(&input).unwrap();
+                let steps = traced.reduce_traced();
+                assert_eq!(traced.to_string(), expected, "{input}");
+                assert!(steps.last().is_none_or(|step| step.after == traced));
+            }
+        }
+    }
+
+    // cargo test --release --bin day18 -- --ignored --nocapture
+    #[test]
+    #[ignore]
+    fn bench_flat() {
+        let input = include_str!("../../inputs/day18.txt").repeat(10);
+        let nums = Pair::parse_multi(&input).unwrap();
+        assert_eq!(nums.len(), 1000);
+        let flats: Vec<Flat> = nums.iter().map(Flat::from_pair).collect();
+
+        let start = std::time::Instant::now();
+        let tree = Pair::sum(&nums).unwrap();
+        let tree_sum = start.elapsed();
+        let start = std::time::Instant::now();
+        let flat = Flat::sum_with(&flats, &SnailfishRules::default()).unwrap();
+        let flat_sum = start.elapsed();
+        assert_eq!(flat.to_pair(), tree);
+        println!("sum of 1000: tree {tree_sum:?}, flat {flat_sum:?}");
+
+        let start = std::time::Instant::now();
+        let tree = Pair::find_largest_magnitude_of_sum_of_two(&nums);
+        let tree_pairs = start.elapsed();
+        let start = std::time::Instant::now();
+        let flat =
+            Flat::find_largest_magnitude_of_sum_of_two_with(&flats, &SnailfishRules::default());
+        let flat_pairs = start.elapsed();
+        assert_eq!(flat, tree);
+        println!("largest of 1000 x 1000 sums: tree {tree_pairs:?}, flat {flat_pairs:?}");
     }
 }
@@ -1,4 +1,5 @@
-use std::collections::HashSet;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::fmt::Write;
 
 use once_cell::sync::Lazy;
 
@@ -22,6 +23,14 @@ impl Vector {
         Self([x, y, z, 1])
     }
 
+    fn offset(&self, to: &Self) -> Self {
+        Self::new(
+            to.0[0] - self.0[0],
+            to.0[1] - self.0[1],
+            to.0[2] - self.0[2],
+        )
+    }
+
     fn distance(&self, other: &Self) -> usize {
         self.0
             .iter()
@@ -61,7 +70,7 @@ impl<'a> IterCol<'a> {
     }
 }
 
-impl<'l, 'r> std::ops::Mul<&'r Matrix> for &'l Matrix {
+impl<'r> std::ops::Mul<&'r Matrix> for &Matrix {
     type Output = Matrix;
 
     fn mul(self, rhs: &'r Matrix) -> Self::Output {
@@ -80,7 +89,7 @@ impl<'l, 'r> std::ops::Mul<&'r Matrix> for &'l Matrix {
     }
 }
 
-impl<'l, 'r> std::ops::Mul<&'r Vector> for &'l Matrix {
+impl<'r> std::ops::Mul<&'r Vector> for &Matrix {
     type Output = Vector;
 
     fn mul(self, rhs: &'r Vector) -> Self::Output {
@@ -105,14 +114,17 @@ impl Matrix {
         Self(matrix)
     }
 
+    #[cfg(test)]
     fn with_x_rot90() -> Self {
         Self::new([[1, 0, 0, 0], [0, 0, -1, 0], [0, 1, 0, 0], [0, 0, 0, 1]])
     }
 
+    #[cfg(test)]
     fn with_y_rot90() -> Self {
         Self::new([[0, 0, 1, 0], [0, 1, 0, 0], [-1, 0, 0, 0], [0, 0, 0, 1]])
     }
 
+    #[cfg(test)]
     fn with_z_rot90() -> Self {
         Self::new([[0, -1, 0, 0], [1, 0, 0, 0], [0, 0, 1, 0], [0, 0, 0, 1]])
     }
@@ -159,107 +171,435 @@ fn parse(input: &str) -> Vec<Vec<Vector>> {
     scanners
 }
 
-static POSSIBLE_ROTATIONS: Lazy<[Matrix; 24]> = Lazy::new(|| {
-    let mut rotations = vec![Matrix::default()];
-    for _ in 0..4 {
-        for new_rotations in [
-            rotations
-                .iter()
-                .map(|r| r * &Matrix::with_x_rot90())
-                .collect::<Vec<Matrix>>(),
-            rotations
-                .iter()
-                .map(|r| r * &Matrix::with_y_rot90())
-                .collect::<Vec<Matrix>>(),
-            rotations
-                .iter()
-                .map(|r| r * &Matrix::with_z_rot90())
-                .collect::<Vec<Matrix>>(),
-        ]
-        .iter_mut()
-        {
-            rotations.append(new_rotations);
-        }
+// directions an axis can end up pointing in after a rotation
+const DIRECTIONS: [[isize; 3]; 6] = [
+    [1, 0, 0],
+    [-1, 0, 0],
+    [0, 1, 0],
+    [0, -1, 0],
+    [0, 0, 1],
+    [0, 0, -1],
+];
+
+// one of the 24 ways a scanner can be turned, numbered by where its x axis points (in the
+// order of DIRECTIONS) and then by which of the four directions left its y axis points,
+// the z axis follows from those two
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+struct Rotation(usize);
+
+// matrices, products and inverses of all the rotations, worked out once
+struct RotationTable {
+    matrices: Vec<Matrix>,
+    lookup: HashMap<Matrix, Rotation>,
+    products: Vec<[Rotation; 24]>,
+    inverses: Vec<Rotation>,
+}
+
+static ROTATIONS: Lazy<RotationTable> = Lazy::new(|| {
+    let matrices: Vec<Matrix> = Rotation::all().map(Rotation::build_matrix).collect();
+    let lookup: HashMap<Matrix, Rotation> =
+        Rotation::all().map(|r| (matrices[r.index()], r)).collect();
+    let products: Vec<[Rotation; 24]> = matrices
+        .iter()
+        .map(|a| std::array::from_fn(|b| lookup[&(a * &matrices[b])]))
+        .collect();
+    let inverses = products
+        .iter()
+        .map(|row| Rotation(row.iter().position(|&r| r == Rotation::IDENTITY).unwrap()))
+        .collect();
+    RotationTable {
+        matrices,
+        lookup,
+        products,
+        inverses,
     }
-    rotations
-        .into_iter()
-        .collect::<HashSet<_>>()
-        .into_iter()
-        .collect::<Vec<_>>()
-        .try_into()
-        .unwrap()
 });
 
-#[derive(Clone, Debug, Default)]
-struct MatchedReadings {
-    transformation: Matrix,
-    unmatched: Vec<Vector>,
+impl Rotation {
+    const IDENTITY: Self = Self(0);
+
+    fn all() -> impl Iterator<Item = Self> {
+        (0..24).filter_map(Self::from_index)
+    }
+
+    fn index(self) -> usize {
+        self.0
+    }
+
+    fn from_index(index: usize) -> Option<Self> {
+        (index < 24).then_some(Self(index))
+    }
+
+    // where the x, y and z axes point after the rotation
+    fn axes(self) -> [[isize; 3]; 3] {
+        let x = DIRECTIONS[self.0 / 4];
+        let y = *DIRECTIONS
+            .iter()
+            .filter(|d| (0..3).all(|i| d[i] * x[i] == 0))
+            .nth(self.0 % 4)
+            .unwrap();
+        let z = [
+            x[1] * y[2] - x[2] * y[1],
+            x[2] * y[0] - x[0] * y[2],
+            x[0] * y[1] - x[1] * y[0],
+        ];
+        [x, y, z]
+    }
+
+    fn build_matrix(self) -> Matrix {
+        let mut matrix = Matrix::default();
+        for (col, axis) in self.axes().iter().enumerate() {
+            for (row, &n) in axis.iter().enumerate() {
+                matrix.0[row][col] = n;
+            }
+        }
+        matrix
+    }
+
+    fn to_matrix(self) -> Matrix {
+        ROTATIONS.matrices[self.0]
+    }
+
+    // the rotation part of a transformation, the translation is ignored
+    fn from_matrix(matrix: &Matrix) -> Option<Self> {
+        let mut rotation = *matrix;
+        for row in rotation.0.iter_mut().take(3) {
+            row[3] = 0;
+        }
+        ROTATIONS.lookup.get(&rotation).copied()
+    }
+
+    fn apply(self, v: &Vector) -> Vector {
+        &self.to_matrix() * v
+    }
+
+    fn inverse(self) -> Self {
+        ROTATIONS.inverses[self.0]
+    }
+
+    // where each of the scanner's axes points on the map, like "x->-y, y->z, z->-x"
+    fn name(self) -> String {
+        let names = ["x", "y", "z"];
+        self.axes()
+            .iter()
+            .zip(names)
+            .map(|(axis, name)| {
+                let i = axis.iter().position(|&n| n != 0).unwrap();
+                let sign = if axis[i] < 0 { "-" } else { "" };
+                format!("{name}->{sign}{}", names[i])
+            })
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
 }
 
-fn match_readings(base_readings: &HashSet<Vector>, readings: &[Vector]) -> MatchedReadings {
-    let mut best_match = MatchedReadings {
-        transformation: Matrix::default(),
-        unmatched: base_readings.iter().cloned().collect(),
-    };
-    for base_vec in base_readings {
-        for vec in readings {
-            for rot in *POSSIBLE_ROTATIONS {
-                let rev_transformation = rot.with_matching_translation(vec, base_vec);
-                let unmatched: Vec<Vector> = readings
-                    .iter()
-                    .map(|v| &rev_transformation * v)
-                    .filter(|v| !base_readings.contains(v))
-                    .collect();
-                if unmatched.len() < best_match.unmatched.len() {
-                    best_match = MatchedReadings {
-                        transformation: rev_transformation,
-                        unmatched,
-                    };
+// like matrices, `a * b` turns by `b` first and then by `a`
+impl std::ops::Mul for Rotation {
+    type Output = Rotation;
+
+    fn mul(self, rhs: Rotation) -> Self::Output {
+        ROTATIONS.products[self.0][rhs.0]
+    }
+}
+
+impl std::fmt::Display for Rotation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.name())
+    }
+}
+
+// scanners overlap when they both see at least this many beacons
+const MIN_OVERLAP: usize = 12;
+
+// squared distances between every two readings of a scanner, they stay the same however the
+// scanner is placed and rotated. Ordered, so a low minimum overlap that several transformations
+// satisfy always gets the same one
+#[derive(Clone, Debug)]
+struct Fingerprint(BTreeMap<isize, Vec<(usize, usize)>>);
+
+impl Fingerprint {
+    fn new(readings: &[Vector]) -> Self {
+        let mut distances: BTreeMap<isize, Vec<(usize, usize)>> = BTreeMap::new();
+        for (i, a) in readings.iter().enumerate() {
+            for (j, b) in readings.iter().enumerate().skip(i + 1) {
+                let squared = (0..3).map(|k| (a.0[k] - b.0[k]).pow(2)).sum();
+                distances.entry(squared).or_default().push((i, j));
+            }
+        }
+        Self(distances)
+    }
+
+    // an overlap of n beacons shows up as at least n * (n - 1) / 2 shared distances
+    fn shared(&self, other: &Self) -> usize {
+        self.0
+            .iter()
+            .filter_map(|(d, pairs)| Some(pairs.len().min(other.0.get(d)?.len())))
+            .sum()
+    }
+}
+
+// finds the transformation putting at least `min_overlap` of `readings` onto `base_readings`,
+// only lining up pairs of readings which are the same distance apart in both scanners, shortest
+// distance first
+fn match_readings(
+    base_readings: &[Vector],
+    base_fingerprint: &Fingerprint,
+    readings: &[Vector],
+    fingerprint: &Fingerprint,
+    min_overlap: usize,
+) -> Option<Matrix> {
+    let base_set: HashSet<Vector> = base_readings.iter().cloned().collect();
+    for (d, base_pairs) in &base_fingerprint.0 {
+        let pairs = match fingerprint.0.get(d) {
+            Some(pairs) => pairs,
+            None => continue,
+        };
+        for &(base_from, base_to) in base_pairs {
+            let base_offset = base_readings[base_from].offset(&base_readings[base_to]);
+            for &(a, b) in pairs {
+                for (from, to) in [(a, b), (b, a)] {
+                    let offset = readings[from].offset(&readings[to]);
+                    for rot in Rotation::all() {
+                        if rot.apply(&offset) != base_offset {
+                            continue;
+                        }
+                        let transformation = rot
+                            .to_matrix()
+                            .with_matching_translation(&readings[from], &base_readings[base_from]);
+                        let matched = readings
+                            .iter()
+                            .filter(|v| base_set.contains(&(&transformation * *v)))
+                            .count();
+                        if matched >= min_overlap {
+                            return Some(transformation);
+                        }
+                    }
                 }
             }
         }
     }
-    best_match
+    None
 }
 
-// very crap solution - repeats a lot of calculations over and over again (full solution takes almost 30mins)
-fn match_all(readings: &[Vec<Vector>]) -> (HashSet<Vector>, Vec<Matrix>) {
-    let mut transformations = vec![None; readings.len()];
-    let mut absolute_readings = HashSet::from_iter(readings.first().unwrap().iter().cloned());
-    let mut matched_readings = HashSet::new();
-    matched_readings.insert(0);
-    transformations[0] = Some(Matrix::default());
-
-    loop {
-        let mut best: Option<(usize, Vec<Vector>, Matrix)> = None;
-        for (idx, readings) in readings.iter().enumerate() {
-            if !matched_readings.contains(&idx) {
-                let candidate = match_readings(&absolute_readings, readings);
-                if best
-                    .as_ref()
-                    .map(|(_, best_unmatched, _)| candidate.unmatched.len() < best_unmatched.len())
-                    .unwrap_or(true)
-                {
-                    best = Some((idx, candidate.unmatched, candidate.transformation));
+// a group of scanners connected by overlaps, everything is relative to the first scanner
+#[derive(Clone, Debug)]
+struct ScannerMap {
+    scanners: Vec<usize>,
+    transformations: Vec<Matrix>,
+    beacons: HashSet<Vector>,
+}
+
+impl ScannerMap {
+    // (scanner, absolute position) by scanner number
+    fn positions(&self) -> Vec<(usize, Vector)> {
+        let mut positions: Vec<(usize, Vector)> = self
+            .scanners
+            .iter()
+            .zip(&self.transformations)
+            .map(|(&idx, m)| (idx, m * &Vector::default()))
+            .collect();
+        positions.sort_by_key(|&(idx, _)| idx);
+        positions
+    }
+
+    fn sorted_beacons(&self) -> Vec<Vector> {
+        let mut beacons: Vec<Vector> = self.beacons.iter().cloned().collect();
+        beacons.sort_by_key(|v| v.0);
+        beacons
+    }
+
+    // (scanner, scanner, shared beacons) for every two scanners seeing at least `min_overlap`
+    // of the same beacons
+    fn overlaps(&self, readings: &[Vec<Vector>], min_overlap: usize) -> Vec<(usize, usize, usize)> {
+        let seen: Vec<HashSet<Vector>> = self
+            .scanners
+            .iter()
+            .zip(&self.transformations)
+            .map(|(&idx, m)| readings[idx].iter().map(|v| m * v).collect())
+            .collect();
+        let mut overlaps = Vec::new();
+        for i in 0..self.scanners.len() {
+            for j in i + 1..self.scanners.len() {
+                let shared = seen[i].intersection(&seen[j]).count();
+                if shared >= min_overlap {
+                    let (a, b) = (self.scanners[i], self.scanners[j]);
+                    overlaps.push((a.min(b), a.max(b), shared));
                 }
             }
         }
-        if let Some((best_idx, best_unmatched, best_transformation)) = best {
-            matched_readings.insert(best_idx);
-            absolute_readings.extend(best_unmatched.into_iter());
-            transformations[best_idx] = Some(best_transformation);
-            println!("{}/{}", matched_readings.len(), readings.len());
-        } else {
-            break;
+        overlaps.sort();
+        overlaps
+    }
+
+    fn report(&self, readings: &[Vec<Vector>], min_overlap: usize) -> String {
+        let overlaps = self.overlaps(readings, min_overlap);
+        let mut report = format!(
+            "map of scanner {}: {} scanners, {} beacons\n",
+            self.scanners[0],
+            self.scanners.len(),
+            self.beacons.len()
+        );
+        for (idx, position) in self.positions() {
+            let transformation =
+                self.transformations[self.scanners.iter().position(|&i| i == idx).unwrap()];
+            let neighbours: Vec<String> = overlaps
+                .iter()
+                .filter_map(|&(a, b, shared)| match (a == idx, b == idx) {
+                    (true, _) => Some(format!("{b} ({shared})")),
+                    (_, true) => Some(format!("{a} ({shared})")),
+                    _ => None,
+                })
+                .collect();
+            writeln!(
+                report,
+                "scanner {idx} at {position:?}, {}, overlaps {}",
+                Rotation::from_matrix(&transformation).unwrap(),
+                neighbours.join(", ")
+            )
+            .unwrap();
+        }
+        report
+    }
+
+    fn to_csv(&self) -> String {
+        let mut csv = String::from("kind,scanner,x,y,z\n");
+        for (idx, v) in self.positions() {
+            writeln!(csv, "scanner,{idx},{},{},{}", v.0[0], v.0[1], v.0[2]).unwrap();
+        }
+        for v in self.sorted_beacons() {
+            writeln!(csv, "beacon,,{},{},{}", v.0[0], v.0[1], v.0[2]).unwrap();
+        }
+        csv
+    }
+
+    // ascii point cloud, scanners in red and beacons in white
+    fn to_ply(&self) -> String {
+        let mut ply = String::new();
+        writeln!(ply, "ply").unwrap();
+        writeln!(ply, "format ascii 1.0").unwrap();
+        writeln!(
+            ply,
+            "element vertex {}",
+            self.scanners.len() + self.beacons.len()
+        )
+        .unwrap();
+        for property in [
+            "int x",
+            "int y",
+            "int z",
+            "uchar red",
+            "uchar green",
+            "uchar blue",
+        ] {
+            writeln!(ply, "property {property}").unwrap();
+        }
+        writeln!(ply, "end_header").unwrap();
+        for (_, v) in self.positions() {
+            writeln!(ply, "{} {} {} 255 0 0", v.0[0], v.0[1], v.0[2]).unwrap();
+        }
+        for v in self.sorted_beacons() {
+            writeln!(ply, "{} {} {} 255 255 255", v.0[0], v.0[1], v.0[2]).unwrap();
+        }
+        ply
+    }
+}
+
+#[derive(Clone, Debug)]
+struct Alignment {
+    // the map of scanner 0, which the puzzle's answers are about
+    placed: ScannerMap,
+    // other groups of scanners overlapping each other but none of `placed`
+    clusters: Vec<ScannerMap>,
+    // scanners overlapping no other scanner at all
+    unplaceable: Vec<usize>,
+}
+
+// places scanners breadth first from `start`, only trying to align scanners whose
+// fingerprints share enough distances
+fn place_from(
+    start: usize,
+    readings: &[Vec<Vector>],
+    fingerprints: &[Fingerprint],
+    transformations: &mut [Option<Matrix>],
+    min_overlap: usize,
+) -> ScannerMap {
+    let min_shared = min_overlap * min_overlap.saturating_sub(1) / 2;
+    let mut map = ScannerMap {
+        scanners: vec![start],
+        transformations: vec![Rotation::IDENTITY.to_matrix()],
+        beacons: readings[start].iter().cloned().collect(),
+    };
+    let mut absolute_readings = vec![Vec::new(); readings.len()];
+    transformations[start] = Some(Rotation::IDENTITY.to_matrix());
+    absolute_readings[start] = readings[start].clone();
+    let mut queue = VecDeque::from([start]);
+    while let Some(base_idx) = queue.pop_front() {
+        for idx in 0..readings.len() {
+            if transformations[idx].is_some()
+                || fingerprints[base_idx].shared(&fingerprints[idx]) < min_shared
+            {
+                continue;
+            }
+            if let Some(transformation) = match_readings(
+                &absolute_readings[base_idx],
+                &fingerprints[base_idx],
+                &readings[idx],
+                &fingerprints[idx],
+                min_overlap,
+            ) {
+                absolute_readings[idx] =
+                    readings[idx].iter().map(|v| &transformation * v).collect();
+                map.beacons.extend(absolute_readings[idx].iter().cloned());
+                map.scanners.push(idx);
+                map.transformations.push(transformation);
+                transformations[idx] = Some(transformation);
+                queue.push_back(idx);
+                // progress goes to stderr so exports on stdout stay clean
+                eprintln!(
+                    "{}/{}",
+                    transformations.iter().flatten().count(),
+                    readings.len()
+                );
+            }
+        }
+    }
+    map
+}
+
+// scanner 0's map first, then whatever is left over gets mapped from its lowest scanner
+fn match_all(readings: &[Vec<Vector>], min_overlap: usize) -> Alignment {
+    let fingerprints: Vec<Fingerprint> = readings.iter().map(|r| Fingerprint::new(r)).collect();
+    let mut transformations = vec![None; readings.len()];
+    let placed = place_from(
+        0,
+        readings,
+        &fingerprints,
+        &mut transformations,
+        min_overlap,
+    );
+    let mut clusters = Vec::new();
+    let mut unplaceable = Vec::new();
+    for start in 0..readings.len() {
+        if transformations[start].is_none() {
+            let map = place_from(
+                start,
+                readings,
+                &fingerprints,
+                &mut transformations,
+                min_overlap,
+            );
+            if map.scanners.len() == 1 {
+                unplaceable.push(start);
+            } else {
+                clusters.push(map);
+            }
         }
     }
-    (
-        absolute_readings,
-        transformations
-            .into_iter()
-            .collect::<Option<Vec<_>>>()
-            .unwrap(),
-    )
+    Alignment {
+        placed,
+        clusters,
+        unplaceable,
+    }
 }
 
 fn biggest_distance(transformations: &[Matrix]) -> usize {
@@ -279,11 +619,61 @@ fn biggest_distance(transformations: &[Matrix]) -> usize {
     max_distance
 }
 
+// usage: day19 [--report|--csv|--ply] [--min-overlap=N] [FILE]
+//        day19 --rotations
+// the answers and exports only cover scanner 0's map, anything that can't be joined to it is
+// reported on stderr
 fn main() {
-    let readings = parse(include_str!("../../inputs/day19.txt"));
-    let (absolute_readings, transformations) = match_all(&readings);
-    println!("Part 1: {}", absolute_readings.len());
-    println!("Part 2: {}", biggest_distance(&transformations));
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let flag = |name: &str| args.iter().any(|arg| arg == name);
+    let option = |prefix: &str| args.iter().find_map(|arg| arg.strip_prefix(prefix));
+    if flag("--rotations") {
+        for r in Rotation::all() {
+            println!("{:2}: {r} (inverse {})", r.index(), r.inverse().index());
+        }
+        return;
+    }
+    let input = match args.iter().find(|arg| !arg.starts_with("--")) {
+        Some(path) => std::fs::read_to_string(path).unwrap(),
+        None => include_str!("../../inputs/day19.txt").to_string(),
+    };
+    let min_overlap = option("--min-overlap=").map_or(MIN_OVERLAP, |n| n.parse().unwrap());
+    let readings = parse(&input);
+    let alignment = match_all(&readings, min_overlap);
+    if flag("--report") {
+        print!("{}", alignment.placed.report(&readings, min_overlap));
+        for cluster in &alignment.clusters {
+            println!();
+            print!("{}", cluster.report(&readings, min_overlap));
+        }
+        if !alignment.unplaceable.is_empty() {
+            println!();
+            println!("unplaceable scanners: {:?}", alignment.unplaceable);
+        }
+        return;
+    }
+    if flag("--csv") {
+        print!("{}", alignment.placed.to_csv());
+        return;
+    }
+    if flag("--ply") {
+        print!("{}", alignment.placed.to_ply());
+        return;
+    }
+    for cluster in &alignment.clusters {
+        eprintln!("scanners {:?} form a separate map", cluster.scanners);
+    }
+    if !alignment.unplaceable.is_empty() {
+        eprintln!(
+            "scanners {:?} overlap no other scanner",
+            alignment.unplaceable
+        );
+    }
+    println!("Part 1: {}", alignment.placed.beacons.len());
+    println!(
+        "Part 2: {}",
+        biggest_distance(&alignment.placed.transformations)
+    );
 }
 
 #[cfg(test)]
@@ -338,21 +728,230 @@ mod tests {
     #[test]
     fn test_possible_rotations() {
         assert_eq!(
-            POSSIBLE_ROTATIONS
-                .iter()
-                .cloned()
+            Rotation::all()
+                .map(Rotation::to_matrix)
                 .collect::<HashSet<Matrix>>()
                 .len(),
-            POSSIBLE_ROTATIONS.len()
+            24
+        );
+    }
+
+    #[test]
+    fn test_fingerprint() {
+        let readings = parse(include_str!("../../inputs/day19-sample.txt"));
+        let fingerprints: Vec<Fingerprint> = readings.iter().map(|r| Fingerprint::new(r)).collect();
+        assert_eq!(
+            fingerprints[0].shared(&fingerprints[0]),
+            readings[0].len() * (readings[0].len() - 1) / 2
         );
+        assert!(fingerprints[0].shared(&fingerprints[1]) >= 66);
+        assert!(fingerprints[1].shared(&fingerprints[4]) >= 66);
+        assert!(fingerprints[0].shared(&fingerprints[2]) < 66);
     }
 
     #[test]
-    #[ignore]
     fn test_sample() {
         let readings = parse(include_str!("../../inputs/day19-sample.txt"));
-        let (absolute_readings, transformations) = match_all(&readings);
-        assert_eq!(absolute_readings.len(), 79);
-        assert_eq!(biggest_distance(&transformations), 3621);
+        let alignment = match_all(&readings, MIN_OVERLAP);
+        assert!(alignment.clusters.is_empty());
+        assert!(alignment.unplaceable.is_empty());
+        let placed = alignment.placed;
+        assert_eq!(placed.scanners, [0, 1, 3, 4, 2]);
+        assert_eq!(placed.beacons.len(), 79);
+        assert_eq!(biggest_distance(&placed.transformations), 3621);
+        let positions: Vec<Vector> = placed.positions().into_iter().map(|(_, v)| v).collect();
+        assert_eq!(
+            positions,
+            [
+                Vector::new(0, 0, 0),
+                Vector::new(68, -1246, -43),
+                Vector::new(1105, -1205, 1229),
+                Vector::new(-92, -2380, -20),
+                Vector::new(-20, -1133, 1061),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_disconnected() {
+        let readings = parse(include_str!("../../inputs/day19-sample.txt"));
+        // scanner 0 only overlaps scanner 1, scanner 3 only overlaps scanner 1 and scanner 2
+        // only overlaps scanner 4
+        let without_1 = [0, 2, 3, 4].map(|idx| readings[idx].clone());
+        let alignment = match_all(&without_1, MIN_OVERLAP);
+        assert_eq!(alignment.placed.scanners, [0]);
+        assert_eq!(alignment.placed.beacons.len(), readings[0].len());
+        assert_eq!(alignment.clusters.len(), 1);
+        assert_eq!(alignment.clusters[0].scanners, [1, 3]);
+        assert_eq!(
+            alignment.clusters[0].beacons.len(),
+            readings[2].len() + readings[4].len() - 12
+        );
+        // manhattan distances survive the rotation into scanner 2's frame
+        assert_eq!(
+            biggest_distance(&alignment.clusters[0].transformations),
+            Vector::new(1105, -1205, 1229).distance(&Vector::new(-20, -1133, 1061))
+        );
+        assert_eq!(alignment.unplaceable, [2]);
+
+        // nothing overlaps by that much
+        let alignment = match_all(&readings, 40);
+        assert_eq!(alignment.placed.scanners, [0]);
+        assert!(alignment.clusters.is_empty());
+        assert_eq!(alignment.unplaceable, [1, 2, 3, 4]);
+
+        // the sample's overlaps are exactly 12 beacons
+        assert_eq!(match_all(&readings, 13).unplaceable, [1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn test_ambiguous_match() {
+        // two pairs 1 and 3 apart, each alone enough for an overlap of 2 with a different
+        // transformation, the shorter distance is tried first
+        let readings = [
+            Vector::new(0, 0, 0),
+            Vector::new(1, 0, 0),
+            Vector::new(100, 0, 0),
+            Vector::new(100, 3, 0),
+        ];
+        let base_readings = [
+            Vector::new(0, 0, 0),
+            Vector::new(1, 0, 0),
+            Vector::new(500, 500, 500),
+            Vector::new(500, 503, 500),
+        ];
+        let matched = match_readings(
+            &base_readings,
+            &Fingerprint::new(&base_readings),
+            &readings,
+            &Fingerprint::new(&readings),
+            2,
+        );
+        assert_eq!(matched, Some(Matrix::default()));
+        let matched = match_readings(
+            &base_readings[2..],
+            &Fingerprint::new(&base_readings[2..]),
+            &readings,
+            &Fingerprint::new(&readings),
+            2,
+        );
+        assert_eq!(
+            matched.map(|matrix| &matrix * &readings[2]),
+            Some(base_readings[2])
+        );
+    }
+
+    #[test]
+    fn test_rotation_names() {
+        assert_eq!(Rotation::IDENTITY.name(), "x->x, y->y, z->z");
+        let name = |matrix: &Matrix| Rotation::from_matrix(matrix).unwrap().to_string();
+        assert_eq!(name(&Matrix::with_z_rot90()), "x->y, y->-x, z->z");
+        assert_eq!(
+            name(&(&Matrix::with_x_rot90() * &Matrix::with_y_rot90())),
+            "x->y, y->z, z->x"
+        );
+        let names: HashSet<String> = Rotation::all().map(Rotation::name).collect();
+        assert_eq!(names.len(), 24);
+    }
+
+    #[test]
+    fn test_rotation_group() {
+        let all: Vec<Rotation> = Rotation::all().collect();
+        assert_eq!(all.len(), 24);
+        for (i, &r) in all.iter().enumerate() {
+            assert_eq!(r.index(), i);
+            assert_eq!(Rotation::from_index(i), Some(r));
+            assert_eq!(Rotation::from_matrix(&r.to_matrix()), Some(r));
+            assert_eq!(r * Rotation::IDENTITY, r);
+            assert_eq!(Rotation::IDENTITY * r, r);
+            assert_eq!(r * r.inverse(), Rotation::IDENTITY);
+            assert_eq!(r.inverse() * r, Rotation::IDENTITY);
+            let v = Vector::new(1, 20, 300);
+            assert_eq!(r.apply(&v), &r.to_matrix() * &v);
+            assert_eq!(r.inverse().apply(&r.apply(&v)), v);
+            for &s in &all {
+                assert_eq!((r * s).to_matrix(), &r.to_matrix() * &s.to_matrix());
+                for &t in &all {
+                    assert_eq!((r * s) * t, r * (s * t));
+                }
+            }
+        }
+        assert_eq!(Rotation::from_index(24), None);
+        assert_eq!(Rotation::from_matrix(&Matrix::new([[2; 4]; 4])), None);
+        // a mirror image isn't a rotation
+        assert_eq!(
+            Rotation::from_matrix(&Matrix::new([
+                [-1, 0, 0, 0],
+                [0, 1, 0, 0],
+                [0, 0, 1, 0],
+                [0, 0, 0, 1]
+            ])),
+            None
+        );
+
+        // the quarter turns around each axis generate all of them
+        let generators = [
+            Matrix::with_x_rot90(),
+            Matrix::with_y_rot90(),
+            Matrix::with_z_rot90(),
+        ];
+        let mut generated = HashSet::from([Matrix::default()]);
+        loop {
+            let next: HashSet<Matrix> = generated
+                .iter()
+                .flat_map(|m| generators.iter().map(move |g| m * g))
+                .chain(generated.iter().cloned())
+                .collect();
+            if next.len() == generated.len() {
+                break;
+            }
+            generated = next;
+        }
+        assert_eq!(
+            generated,
+            Rotation::all().map(Rotation::to_matrix).collect()
+        );
+    }
+
+    #[test]
+    fn test_report() {
+        let readings = parse(include_str!("../../inputs/day19-sample.txt"));
+        let placed = match_all(&readings, MIN_OVERLAP).placed;
+        assert_eq!(
+            placed.overlaps(&readings, MIN_OVERLAP),
+            [(0, 1, 12), (1, 3, 12), (1, 4, 12), (2, 4, 12)]
+        );
+        let report = placed.report(&readings, MIN_OVERLAP);
+        let mut lines = report.lines();
+        assert_eq!(
+            lines.next(),
+            Some("map of scanner 0: 5 scanners, 79 beacons")
+        );
+        assert_eq!(
+            lines.next(),
+            Some("scanner 0 at (0,0,0), x->x, y->y, z->z, overlaps 1 (12)")
+        );
+        assert_eq!(
+            lines.next(),
+            Some(
+                "scanner 1 at (68,-1246,-43), x->-x, y->y, z->-z, overlaps 0 (12), 3 (12), 4 (12)"
+            )
+        );
+        assert_eq!(report.lines().count(), 6);
+
+        let csv = placed.to_csv();
+        assert_eq!(csv.lines().count(), 1 + 5 + 79);
+        assert!(csv.contains("\nscanner,1,68,-1246,-43\n"));
+        assert!(csv.contains("\nbeacon,,404,-588,-901\n"));
+
+        let ply = placed.to_ply();
+        assert!(ply.starts_with("ply\nformat ascii 1.0\nelement vertex 84\n"));
+        let body: Vec<&str> = ply
+            .lines()
+            .skip_while(|l| *l != "end_header")
+            .skip(1)
+            .collect();
+        assert_eq!(body.len(), 84);
+        assert_eq!(body[1], "68 -1246 -43 255 0 0");
     }
 }
@@ -2,11 +2,7 @@ use std::collections::{HashMap, VecDeque};
 
 #[inline]
 fn dist(a: usize, b: usize) -> usize {
-    if a < b {
-        b - a
-    } else {
-        a - b
-    }
+    a.abs_diff(b)
 }
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]